@@ -0,0 +1,66 @@
+//! Compares `BucketArray` against its previous layout, a vector with free indices in a `BTreeSet`.
+//! Run with `cargo run --release --example bucket_array_bench`
+
+#[allow(dead_code)]
+#[path = "../src/bucket_array.rs"]
+mod bucket_array;
+
+use bucket_array::BucketArray;
+use std::collections::BTreeSet;
+use std::time::Instant;
+
+/// The previous layout
+struct BTreeLayout<T>
+{
+    items: Vec<T>,
+    free: BTreeSet<usize>
+}
+
+impl<T> BTreeLayout<T>
+{
+    fn insert(&mut self, item: T) -> usize
+    {
+        if let Some(idx) = self.free.iter().next().copied()
+        {
+            self.free.remove(&idx);
+            self.items[idx] = item;
+            idx
+        }
+        else
+        {
+            self.items.push(item);
+            self.items.len()-1
+        }
+    }
+
+    fn remove(&mut self, id: usize)
+    {
+        self.free.insert(id);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (usize, &T)>
+    {
+        self.items.iter().enumerate().filter(move |(id, _)| !self.free.contains(id))
+    }
+}
+
+fn main()
+{
+    const COUNT: usize = 200_000;
+
+    let start = Instant::now();
+    let mut old = BTreeLayout { items: vec![], free: BTreeSet::new() };
+    let ids: Vec<_> = (0..COUNT).map(|e| old.insert(e)).collect();
+    for id in ids.iter().step_by(2) { old.remove(*id); }
+    let sum: usize = old.iter().map(|(_, v)| *v).sum();
+    for e in 0..COUNT/2 { old.insert(e); }
+    println!("btree layout: {:?} ({})", start.elapsed(), sum);
+
+    let start = Instant::now();
+    let mut new = BucketArray::new();
+    let ids: Vec<_> = (0..COUNT).map(|e| new.insert(e)).collect();
+    for id in ids.iter().step_by(2) { new.remove(*id); }
+    let sum: usize = new.iter().map(|(_, v)| *v).sum();
+    for e in 0..COUNT/2 { new.insert(e); }
+    println!("free list layout: {:?} ({})", start.elapsed(), sum);
+}
@@ -101,8 +101,36 @@ impl<T: std::ops::Add<Output = T>> Rect<T>
     }
 }
 
+impl<T: PartialOrd + std::ops::Add<Output = T> + std::ops::Sub<Output = T> + Copy> Rect<T>
+{
+    /// Smallest rectangle containing both
+    pub fn union(self, other: Self) -> Self
+    {
+        let min = |a: T, b: T| if a < b { a } else { b };
+        let max = |a: T, b: T| if a > b { a } else { b };
+        let pos = Vec2::new(min(self.pos.x, other.pos.x), min(self.pos.y, other.pos.y));
+        let end = Vec2::new(max(self.pos.x + self.size.x, other.pos.x + other.size.x), max(self.pos.y + self.size.y, other.pos.y + other.size.y));
+        Rect { pos, size: Vec2::new(end.x - pos.x, end.y - pos.y) }
+    }
+
+    /// Area covered by both, empty at the corner of the overlap if they don't overlap
+    pub fn intersection(self, other: Self) -> Self
+    {
+        let min = |a: T, b: T| if a < b { a } else { b };
+        let max = |a: T, b: T| if a > b { a } else { b };
+        let pos = Vec2::new(max(self.pos.x, other.pos.x), max(self.pos.y, other.pos.y));
+        let end = Vec2::new(min(self.pos.x + self.size.x, other.pos.x + other.size.x), min(self.pos.y + self.size.y, other.pos.y + other.size.y));
+        Rect { pos, size: Vec2::new(max(end.x, pos.x) - pos.x, max(end.y, pos.y) - pos.y) }
+    }
+
+    pub fn contains(self, point: Vec2<T>) -> bool
+    {
+        point.x >= self.pos.x && point.y >= self.pos.y && point.x < self.pos.x + self.size.x && point.y < self.pos.y + self.size.y
+    }
+}
+
 pub type Rectf = Rect<f32>;
 pub type Vec2f = Vec2<f32>;
 pub type Recti = Rect<i32>;
 pub type Vec2i = Vec2<i32>;
-pub type Id = usize;
+pub type Id = crate::bucket_array::Handle;
@@ -1,100 +1,229 @@
-use std::collections::btree_set::*;
-type Id = usize;
+/// Index of an item together with the generation of its slot, the generation
+/// changes on removal so handles to removed items never point at new ones
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Handle
+{
+    index: usize,
+    generation: u32
+}
+
+impl Handle
+{
+    pub fn index(self) -> usize
+    {
+        self.index
+    }
+}
+
+type Id = Handle;
+
+#[derive(Debug)]
+enum Slot<T>
+{
+    Occupied { generation: u32, value: T },
+    // Vacant slots are linked together, so finding a free one is O(1)
+    Vacant { generation: u32, next_free: Option<usize> }
+}
 
 #[derive(Debug)]
 pub struct BucketArray<T>
 {
-    items: Vec<T>,
-    free: BTreeSet<usize>
+    slots: Vec<Slot<T>>,
+    free_head: Option<usize>,
+    len: usize
 }
 
 impl<T> BucketArray<T>
 {
     pub fn new() -> Self
     {
-        Self { items: vec![], free: BTreeSet::new() }
+        Self { slots: vec![], free_head: None, len: 0 }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self
+    {
+        Self { slots: Vec::with_capacity(capacity), free_head: None, len: 0 }
     }
 
     pub fn len(&self) -> usize
     {
-        self.items.len()-self.free.len()
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
     }
 
-    pub fn insert(&mut self, item: T) -> Id 
+    /// Reuses the most recently freed slot if there's any
+    pub fn insert(&mut self, item: T) -> Id
     {
-        if self.free.len() > 0
+        self.len += 1;
+        if let Some(idx) = self.free_head
         {
-            let idx = *self.free.iter().next().unwrap();
-            self.free.remove(&idx);
-            self.items[idx] = item;
-            idx
+            let (generation, next_free) = match self.slots[idx]
+            {
+                Slot::Vacant { generation, next_free } => (generation, next_free),
+                Slot::Occupied { .. } => unreachable!()
+            };
+            self.free_head = next_free;
+            self.slots[idx] = Slot::Occupied { generation, value: item };
+            Handle { index: idx, generation }
         }
-        else 
+        else
         {
-            self.items.push(item);
-            self.items.len()-1
+            self.slots.push(Slot::Occupied { generation: 0, value: item });
+            Handle { index: self.slots.len()-1, generation: 0 }
         }
     }
 
     pub fn get(&self, id: Id) -> Option<&T>
     {
-        if self.free.contains(&id)
-        {
-            None
-        }
-        else 
+        match self.slots.get(id.index)
         {
-            self.items.get(id)
+            Some(Slot::Occupied { generation, value }) if *generation == id.generation => Some(value),
+            _ => None
         }
     }
 
+    /// False for handles of removed items, even if their slot is reused
     pub fn has(&self, id: Id) -> bool
     {
-        id < self.items.len() && !self.free.contains(&id)
+        self.get(id).is_some()
     }
-        
+
 
     pub fn get_mut(&mut self, id: Id) -> Option<&mut T>
     {
-        if self.free.contains(&id)
+        match self.slots.get_mut(id.index)
         {
-            None
+            Some(Slot::Occupied { generation, value }) if *generation == id.generation => Some(value),
+            _ => None
         }
-        else 
+    }
+
+    /// Returns the removed item
+    pub fn remove(&mut self, id: Id) -> T
+    {
+        if !self.has(id)
+        {
+            panic!("Removed non existent value from bucket_array");
+        }
+        let vacant = Slot::Vacant { generation: id.generation.wrapping_add(1), next_free: self.free_head };
+        self.free_head = Some(id.index);
+        self.len -= 1;
+        match std::mem::replace(&mut self.slots[id.index], vacant)
         {
-            self.items.get_mut(id)
+            Slot::Occupied { value, .. } => value,
+            Slot::Vacant { .. } => unreachable!()
         }
     }
 
-    // This assumes you won't need the item
-    pub fn remove(&mut self, id: Id)
+    /// Removes all the items for which `keep` returns false
+    pub fn retain<F: FnMut(Id, &mut T) -> bool>(&mut self, mut keep: F)
     {
-        if self.has(id)
+        for index in 0..self.slots.len()
         {
-            self.free.insert(id);
+            let id = match &mut self.slots[index]
+            {
+                Slot::Occupied { generation, value } =>
+                {
+                    let id = Handle { index, generation: *generation };
+                    if keep(id, value) { continue; }
+                    id
+                },
+                Slot::Vacant { .. } => continue
+            };
+            self.remove(id);
         }
-        else
+    }
+
+    /// Removes all the items and returns them, handles to them are no longer valid
+    pub fn drain(&mut self) -> std::vec::IntoIter<(Id, T)>
+    {
+        let mut drained = Vec::with_capacity(self.len);
+        for index in 0..self.slots.len()
         {
-            panic!("Removed non existent value from bucket_array");
+            if let Slot::Occupied { generation, .. } = self.slots[index]
+            {
+                let id = Handle { index, generation };
+                drained.push((id, self.remove(id)));
+            }
         }
+        drained.into_iter()
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.drain();
     }
 
     pub fn iter(&self) -> Iter<'_, T>
     {
-        Iter { handle: self, id: 0 }
+        Iter { slots: self.slots.iter().enumerate() }
     }
 
     pub fn iter_mut(&mut self) -> IterMut<'_, T>
     {
-        IterMut { handle: self, id: 0 }
+        IterMut { slots: self.slots.iter_mut().enumerate() }
+    }
+}
+
+impl<T> std::ops::Index<Id> for BucketArray<T>
+{
+    type Output = T;
+
+    fn index(&self, id: Id) -> &T
+    {
+        self.get(id).expect("Accessed non existent value in bucket_array")
+    }
+}
+
+impl<T> std::ops::IndexMut<Id> for BucketArray<T>
+{
+    fn index_mut(&mut self, id: Id) -> &mut T
+    {
+        self.get_mut(id).expect("Accessed non existent value in bucket_array")
+    }
+}
+
+impl<T> Extend<T> for BucketArray<T>
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I)
+    {
+        for item in iter
+        {
+            self.insert(item);
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a BucketArray<T>
+{
+    type Item = (Id, &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T>
+    {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut BucketArray<T>
+{
+    type Item = (Id, &'a mut T);
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> IterMut<'a, T>
+    {
+        self.iter_mut()
     }
 }
 
 
 pub struct IterMut<'a, T: 'a>
 {
-    handle: &'a mut BucketArray<T>,
-    id: Id
+    slots: std::iter::Enumerate<std::slice::IterMut<'a, Slot<T>>>
 }
 
 impl<'a, T> Iterator for IterMut<'a, T>
@@ -103,32 +232,20 @@ impl<'a, T> Iterator for IterMut<'a, T>
 
     fn next(&mut self) -> Option<Self::Item>
     {
-        unsafe {
-            while self.handle.free.contains(&self.id)
-            {
-                self.id += 1;  
-            }
-
-            let r = if self.id < self.handle.items.len()
+        for (index, slot) in &mut self.slots
+        {
+            if let Slot::Occupied { generation, value } = slot
             {
-                self.handle.items.as_mut_ptr().offset(self.id as isize)
+                return Some((Handle { index, generation: *generation }, value));
             }
-            else 
-            {
-                return None;
-            };
-
-            let id = self.id;
-            self.id += 1;
-            Some((id, &mut *r))
         }
+        None
     }
 }
 
 pub struct Iter<'a, T>
 {
-    handle: &'a BucketArray<T>,
-    id: Id
+    slots: std::iter::Enumerate<std::slice::Iter<'a, Slot<T>>>
 }
 
 impl<'a, T> Iterator for Iter<'a, T>
@@ -137,43 +254,37 @@ impl<'a, T> Iterator for Iter<'a, T>
 
     fn next(&mut self) -> Option<(Id, &'a T)>
     {
-        while self.handle.free.contains(&self.id)
-        {
-            self.id += 1;  
-        }
-        let r = self.handle.get(self.id);
-        let id = self.id;
-        self.id += 1;
-        if let Some(x) = r 
-        {
-            Some((id, x))
-        }
-        else 
+        for (index, slot) in &mut self.slots
         {
-            return None;
+            if let Slot::Occupied { generation, value } = slot
+            {
+                return Some((Handle { index, generation: *generation }, value));
+            }
         }
+        None
     }
 }
 
 #[test]
 fn test()
 {
+    let missing = Handle { index: 4345, generation: 0 };
     let mut b = BucketArray::<usize>::new();
-    b.insert(1);
+    let i0 = b.insert(1);
     b.insert(4000);
     b.insert(5445);
     b.insert(342);
     let i1 = b.insert(343);
     let i2 = b.insert(23);
-    b.remove(0);
-    b.remove(4);
+    assert_eq!(b.remove(i1), 343);
+    assert_eq!(b.remove(i0), 1);
     assert!(b.has(i2));
-    assert!(b.has(4345) == false);
+    assert!(b.has(missing) == false);
     assert_eq!(b.get(i1), None);
-    assert_eq!(b.get(434), None);
+    assert_eq!(b.get(missing), None);
     assert_eq!(b.get(i2), Some(&23));
     *b.get_mut(i2).unwrap() = 0;
-    assert_eq!(b.get_mut(23245345), None);
+    assert_eq!(b.get_mut(missing), None);
     assert_eq!(b.len(), 4);
     {
         let mut it = b.iter();
@@ -183,21 +294,27 @@ fn test()
         let a3 = it.next().unwrap();
         let a4 = it.next().unwrap();
         assert_eq!(*a1.1, 4000);
-        assert_eq!(a1.0, 1);
+        assert_eq!(a1.0.index(), 1);
         assert_eq!(*a2.1, 5445);
-        assert_eq!(a2.0, 2);
+        assert_eq!(a2.0.index(), 2);
         assert_eq!(*a3.1, 342);
-        assert_eq!(a3.0, 3);
+        assert_eq!(a3.0.index(), 3);
         assert_eq!(*a4.1, 0);
-        assert_eq!(a4.0, 5);
+        assert_eq!(a4.0, i2);
         assert_eq!(it.next(), None);
     }
     dbg!(&b);
     for (_k, v) in b.iter_mut() {
         *v = 123;
     }
-    b.insert(3);
+    let i3 = b.insert(3);
     assert_eq!(b.len(), 5);
+    // The slot is reused, but stale handle doesn't see the new item
+    assert_eq!(i3.index(), i0.index());
+    assert!(b.has(i3));
+    assert!(!b.has(i0));
+    assert_eq!(b.get(i0), None);
+    assert_eq!(b.get(i3), Some(&3));
     {
         let mut it = b.iter();
         assert_eq!(*it.next().unwrap().1, 3);
@@ -209,3 +326,46 @@ fn test()
     }
     dbg!(&b);
 }
+
+#[test]
+fn test_collection_traits()
+{
+    use std::rc::Rc;
+
+    // Removed values have to be dropped right away
+    let counter = Rc::new(());
+    let mut b = BucketArray::with_capacity(4);
+    let i0 = b.insert(counter.clone());
+    b.insert(counter.clone());
+    assert_eq!(Rc::strong_count(&counter), 3);
+    b.remove(i0);
+    assert_eq!(Rc::strong_count(&counter), 2);
+    b.clear();
+    assert_eq!(Rc::strong_count(&counter), 1);
+    assert!(b.is_empty());
+
+    let mut b = BucketArray::new();
+    b.extend(0..10_usize);
+    assert_eq!(b.len(), 10);
+    let ids: Vec<_> = (&b).into_iter().map(|(id, _)| id).collect();
+    b[ids[3]] += 100;
+    assert_eq!(b[ids[3]], 103);
+    for (_, v) in &mut b {
+        *v *= 2;
+    }
+    assert_eq!(b[ids[9]], 18);
+
+    b.retain(|_, v| *v % 4 == 0);
+    assert_eq!(b.len(), 5);
+    assert!(!b.has(ids[1]));
+    assert_eq!((&b).into_iter().map(|(_, v)| *v).collect::<Vec<_>>(), vec![0, 4, 8, 12, 16]);
+
+    let drained: Vec<_> = b.drain().collect();
+    assert_eq!(drained.len(), 5);
+    assert_eq!(drained[0], (ids[0], 0));
+    assert!(b.is_empty());
+    assert!(!b.has(ids[0]));
+    // Every slot is reused before growing
+    b.extend(0..10_usize);
+    assert_eq!(b.slots.len(), 10);
+}
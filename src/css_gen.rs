@@ -1,6 +1,9 @@
 use crate::css_parser::*;
+use crate::font::FontFace;
 
-use crate::ui::{StyleRule, StyleRuleTag, DisplayType, StretchType};
+use crate::ui::{Style, StyleRule, StyleRuleTag, DisplayType, StretchType, BorderStyle, Dimension, BoxSizing};
+use crate::ui::{FlexDirection, FlexWrap, JustifyContent, AlignItems, TrackSize, GridLine, Position, WhiteSpace, WordBreak};
+use crate::ui::{FontWeight, FontStyle, LineHeight, Overflow};
 
 enum ExpectingValue {
     Unit,
@@ -18,65 +21,623 @@ impl<'a> Generator<'a> {
     }
 }
 
-fn params_into_offset(params: &[CssRuleParam]) -> Option<StyleRule> {
+// One, two or four values, in the left, top, right, bottom order
+fn params_into_sides<T: Clone>(params: &[CssRuleParam], value: fn(&CssRuleParam) -> Option<T>) -> Option<[T; 4]> {
     match params.len() {
         1 => {
-            let p = params.get(0)?.into_px()?;
-            Some(StyleRule::Offset { l: p, t: p, r: p, b: p })
+            let p = value(params.get(0)?)?;
+            Some([p.clone(), p.clone(), p.clone(), p])
         }
         2 => {
-            let h = params.get(0)?.into_px()?;
-            let v = params.get(1)?.into_px()?;
-            Some(StyleRule::Offset { l: h, t: v, r: h, b: v })
+            let h = value(params.get(0)?)?;
+            let v = value(params.get(1)?)?;
+            Some([h.clone(), v.clone(), h, v])
         }
         4 => {
-            let l = params.get(0)?.into_px()?;
-            let t = params.get(1)?.into_px()?;
-            let r = params.get(2)?.into_px()?;
-            let b = params.get(3)?.into_px()?;
-            Some(StyleRule::Offset { l, t, r, b })
+            let l = value(params.get(0)?)?;
+            let t = value(params.get(1)?)?;
+            let r = value(params.get(2)?)?;
+            let b = value(params.get(3)?)?;
+            Some([l, t, r, b])
         }
         _ => None
     }
 }
 
-fn generate_rule(rule: &CssRule) -> Option<(StyleRuleTag, StyleRule)> {
+fn param_into_length(param: &CssRuleParam) -> Option<StyleRule> {
+    Some(StyleRule::Length(param.into_px()?))
+}
+
+fn param_into_color(param: &CssRuleParam) -> Option<StyleRule> {
+    Some(StyleRule::Color { color: param.into_color()? })
+}
+
+fn param_into_overflow(param: &CssRuleParam) -> Option<StyleRule> {
+    Some(StyleRule::Overflow(match param.into_ident()? {
+        "visible" => Overflow::Visible,
+        "hidden" | "clip" => Overflow::Hidden,
+        "scroll" => Overflow::Scroll,
+        "auto" => Overflow::Auto,
+        _ => None?
+    }))
+}
+
+fn param_into_border_style(param: &CssRuleParam) -> Option<StyleRule> {
+    Some(StyleRule::BorderStyle(match param.into_ident()? {
+        "none" => BorderStyle::None,
+        "solid" => BorderStyle::Solid,
+        _ => None?
+    }))
+}
+
+// `keyword` is what the property accepts instead of a length, `auto` or `none`
+fn param_into_dimension(param: &CssRuleParam, keyword: &str) -> Option<StyleRule> {
+    Some(StyleRule::Dimension(if let Some(px) = param.into_px() {
+        Dimension::Px(px)
+    } else if let Some(percent) = param.into_percent() {
+        Dimension::Percent(percent)
+    } else if param.into_ident()? == keyword {
+        Dimension::Auto
+    } else {
+        None?
+    }))
+}
+
+// Width, style and color in any order, the omitted ones are reset to initial values
+fn params_into_border(params: &[CssRuleParam]) -> Option<[StyleRule; 3]> {
+    let mut result = [None, None, None];
+    if params.is_empty() || params.len() > 3 {
+        return None
+    }
+    for param in params {
+        let (slot, value) = if let Some(v) = param_into_length(param) {
+            (0, v)
+        } else if let Some(v) = param_into_border_style(param) {
+            (1, v)
+        } else {
+            (2, param_into_color(param)?)
+        };
+        if result[slot].is_some() {
+            return None
+        }
+        result[slot] = Some(value);
+    }
+    let [width, style, color] = result;
+    Some([
+        width.unwrap_or(StyleRuleTag::BorderLeftWidth.initial()),
+        style.unwrap_or(StyleRuleTag::BorderLeftStyle.initial()),
+        color.unwrap_or(StyleRuleTag::BorderLeftColor.initial()),
+    ])
+}
+
+// `none`, `auto` or grow, shrink and basis where only grow is required
+fn params_into_flex(params: &[CssRuleParam]) -> Option<Vec<StyleRule>> {
+    let keyword = |grow: f32, shrink: f32| Some(vec![StyleRule::Number(grow), StyleRule::Number(shrink), StyleRule::Dimension(Dimension::Auto)]);
+    match params.get(0)?.into_ident() {
+        Some("none") if params.len() == 1 => return keyword(0., 0.),
+        Some("auto") if params.len() == 1 => return keyword(1., 1.),
+        _ => {}
+    }
+    let grow = params.get(0)?.into_number()?;
+    let (shrink, basis) = match &params[1..] {
+        [] => (1., None),
+        [shrink] if shrink.into_number().is_some() => (shrink.into_number()?, None),
+        [basis] => (1., Some(basis)),
+        [shrink, basis] => (shrink.into_number()?, Some(basis)),
+        _ => None?
+    };
+    let basis = match basis {
+        Some(basis) => param_into_dimension(basis, "auto")?,
+        None => StyleRule::Dimension(Dimension::Px(0.))
+    };
+    Some(vec![StyleRule::Number(grow), StyleRule::Number(shrink), basis])
+}
+
+fn param_into_track(param: &CssRuleParam) -> Option<TrackSize> {
+    Some(match param {
+        CssRuleParam::Unit(v, CssUnit::Px) => TrackSize::Px(*v),
+        CssRuleParam::Unit(v, CssUnit::Percent) => TrackSize::Percent(*v),
+        CssRuleParam::Unit(v, CssUnit::Fr) => TrackSize::Fr(*v),
+        CssRuleParam::UnknownIdent(ident) if ident == "auto" => TrackSize::Auto,
+        _ => None?
+    })
+}
+
+// `none` or a list of tracks, where `repeat(count, tracks)` stands for the tracks repeated
+fn params_into_tracks(params: &[CssRuleParam]) -> Option<StyleRule> {
+    if params.len() == 1 && params[0].into_ident() == Some("none") {
+        return Some(StyleRule::Tracks(vec![]))
+    }
+    let mut tracks = vec![];
+    for param in params {
+        match param {
+            CssRuleParam::Function(name, args) if name == "repeat" => {
+                let (count, repeated) = match args.as_slice() {
+                    [count, repeated] if count.len() == 1 && !repeated.is_empty() => (count[0].into_number()?, repeated),
+                    _ => None?
+                };
+                if count < 1. || count.fract() != 0. {
+                    return None
+                }
+                let repeated = repeated.iter().map(param_into_track).collect::<Option<Vec<_>>>()?;
+                for _ in 0..count as usize {
+                    tracks.extend(repeated.iter().copied());
+                }
+            },
+            _ => tracks.push(param_into_track(param)?)
+        }
+    }
+    Some(StyleRule::Tracks(tracks))
+}
+
+// `none` or one string for every row, all rows need the same number of cells
+fn params_into_areas(params: &[CssRuleParam]) -> Option<StyleRule> {
+    if params.len() == 1 && params[0].into_ident() == Some("none") {
+        return Some(StyleRule::Areas(vec![]))
+    }
+    let rows = params.iter()
+        .map(|e| Some(e.into_string()?.split_whitespace().map(|e| e.to_string()).collect::<Vec<_>>()))
+        .collect::<Option<Vec<_>>>()?;
+    if rows.is_empty() || rows.iter().any(|e| e.is_empty() || e.len() != rows[0].len()) {
+        return None
+    }
+    Some(StyleRule::Areas(rows))
+}
+
+// Comma separated names, each either quoted or a run of identifiers
+fn params_into_families(params: &[CssRuleParam]) -> Option<Vec<String>> {
+    params.split(|e| *e == CssRuleParam::Comma)
+        .map(|name| match name {
+            [CssRuleParam::String(s)] => Some(s.clone()),
+            [] => None,
+            idents => Some(idents.iter().map(|e| e.into_ident()).collect::<Option<Vec<_>>>()?.join(" "))
+        })
+        .collect()
+}
+
+// `auto`, a line number, `span` with a count, or the name of an area
+fn params_into_grid_line(params: &[CssRuleParam]) -> Option<GridLine> {
+    let integer = |param: &CssRuleParam| param.into_number().filter(|e| e.fract() == 0. && *e != 0.).map(|e| e as i32);
+    Some(match params {
+        [CssRuleParam::UnknownIdent(ident)] if ident == "auto" => GridLine::Auto,
+        [CssRuleParam::UnknownIdent(ident)] if ident != "span" => GridLine::Area(ident.clone()),
+        [line] => GridLine::Line(integer(line)?),
+        [span, count] | [count, span] if span.into_ident() == Some("span") => GridLine::Span(integer(count).filter(|e| *e > 0)? as u32),
+        _ => None?
+    })
+}
+
+// Lines separated by slashes, omitted ones are `auto` or the same area as the first one
+fn params_into_grid_lines(params: &[CssRuleParam], count: usize) -> Option<Vec<StyleRule>> {
+    let lines = params.split(|e| *e == CssRuleParam::Slash).map(params_into_grid_line).collect::<Option<Vec<_>>>()?;
+    if lines.len() > count {
+        return None
+    }
+    let omitted = match &lines[0] {
+        GridLine::Area(name) => GridLine::Area(name.clone()),
+        _ => GridLine::Auto
+    };
+    Some((0..count).map(|i| StyleRule::GridLine(lines.get(i).cloned().unwrap_or_else(|| omitted.clone()))).collect())
+}
+
+/// Longhands set by a shorthand property, in the order `shorthand_values` gives the values in
+fn shorthand_longhands(name: &str) -> Option<Vec<StyleRuleTag>> {
+    let side = |i: usize| vec![StyleRuleTag::BORDER_WIDTH[i], StyleRuleTag::BORDER_STYLE[i], StyleRuleTag::BORDER_COLOR[i]];
+    Some(match name {
+        "padding" => StyleRuleTag::PADDING.to_vec(),
+        "margin" => StyleRuleTag::MARGIN.to_vec(),
+        "inset" => StyleRuleTag::INSET.to_vec(),
+        "border-width" => StyleRuleTag::BORDER_WIDTH.to_vec(),
+        "border-style" => StyleRuleTag::BORDER_STYLE.to_vec(),
+        "border-color" => StyleRuleTag::BORDER_COLOR.to_vec(),
+        "border" => (0..4).flat_map(side).collect(),
+        "border-left" => side(0),
+        "border-top" => side(1),
+        "border-right" => side(2),
+        "border-bottom" => side(3),
+        "gap" => vec![StyleRuleTag::RowGap, StyleRuleTag::ColumnGap],
+        "overflow" => vec![StyleRuleTag::OverflowX, StyleRuleTag::OverflowY],
+        "flex" => vec![StyleRuleTag::FlexGrow, StyleRuleTag::FlexShrink, StyleRuleTag::FlexBasis],
+        "grid-column" => vec![StyleRuleTag::GridColumnStart, StyleRuleTag::GridColumnEnd],
+        "grid-row" => vec![StyleRuleTag::GridRowStart, StyleRuleTag::GridRowEnd],
+        "grid-area" => vec![StyleRuleTag::GridRowStart, StyleRuleTag::GridColumnStart, StyleRuleTag::GridRowEnd, StyleRuleTag::GridColumnEnd],
+        _ => return None
+    })
+}
+
+fn shorthand_values(name: &str, params: &[CssRuleParam]) -> Option<Vec<StyleRule>> {
+    Some(match name {
+        "padding" | "margin" | "border-width" => params_into_sides(params, param_into_length)?.to_vec(),
+        "border-style" => params_into_sides(params, param_into_border_style)?.to_vec(),
+        "border-color" => params_into_sides(params, param_into_color)?.to_vec(),
+        "inset" => params_into_sides(params, |e| param_into_dimension(e, "auto"))?.to_vec(),
+        "border" => params_into_border(params)?.iter().cycle().take(12).cloned().collect(),
+        "gap" => match params {
+            [gap] => vec![param_into_length(gap)?; 2],
+            [row, column] => vec![param_into_length(row)?, param_into_length(column)?],
+            _ => None?
+        },
+        "overflow" => match params {
+            [both] => vec![param_into_overflow(both)?; 2],
+            [x, y] => vec![param_into_overflow(x)?, param_into_overflow(y)?],
+            _ => None?
+        },
+        "flex" => params_into_flex(params)?,
+        "grid-column" | "grid-row" => params_into_grid_lines(params, 2)?,
+        "grid-area" => params_into_grid_lines(params, 4)?,
+        _ => params_into_border(params)?.to_vec()
+    })
+}
+
+// CSS-wide keywords are accepted by every property
+fn params_into_keyword(params: &[CssRuleParam]) -> Option<Style> {
+    if params.len() != 1 {
+        return None
+    }
+    Some(match params[0].into_ident()? {
+        "inherit" => Style::Inherit,
+        "initial" => Style::Initial,
+        "unset" => Style::Unset,
+        _ => None?
+    })
+}
+
+// Shorthands expand into all of their longhands, so a later longhand overrides just its part
+fn generate_rule(rule: &CssRule) -> Option<Vec<(StyleRuleTag, Style)>> {
+    if let Some(longhands) = shorthand_longhands(rule.name.as_str()) {
+        if let Some(keyword) = params_into_keyword(&rule.params) {
+            return Some(longhands.into_iter().map(|e| (e, keyword.clone())).collect())
+        }
+        let values = shorthand_values(rule.name.as_str(), &rule.params)?;
+        return Some(longhands.into_iter().zip(values.into_iter().map(Style::Value)).collect())
+    }
     let n = StyleRuleTag::from(rule.name.as_str())?;
-    Some((n, match n {
-        StyleRuleTag::BackgroundColor | StyleRuleTag::Color => {
-            StyleRule::Color { color: rule.params.get(0)?.into_color()? }
+    if let Some(keyword) = params_into_keyword(&rule.params) {
+        return Some(vec![(n, keyword)])
+    }
+    // Properties taking a list of values
+    let list = match n {
+        StyleRuleTag::GridTemplateColumns | StyleRuleTag::GridTemplateRows => Some(params_into_tracks(&rule.params)?),
+        StyleRuleTag::GridTemplateAreas => Some(params_into_areas(&rule.params)?),
+        StyleRuleTag::FontFamily => Some(StyleRule::FontFamily(params_into_families(&rule.params)?)),
+        StyleRuleTag::GridColumnStart | StyleRuleTag::GridColumnEnd | StyleRuleTag::GridRowStart | StyleRuleTag::GridRowEnd => {
+            Some(StyleRule::GridLine(params_into_grid_line(&rule.params)?))
+        },
+        _ => None
+    };
+    if let Some(value) = list {
+        return Some(vec![(n, Style::Value(value))])
+    }
+    if rule.params.len() != 1 {
+        return None
+    }
+    let param = &rule.params[0];
+    Some(vec![(n, Style::Value(match n {
+        StyleRuleTag::BackgroundColor | StyleRuleTag::Color => param_into_color(param)?,
+        _ if StyleRuleTag::BORDER_COLOR.contains(&n) => param_into_color(param)?,
+        _ if StyleRuleTag::BORDER_STYLE.contains(&n) => param_into_border_style(param)?,
+        // Paddings, margins and border widths
+        StyleRuleTag::PaddingLeft | StyleRuleTag::PaddingTop | StyleRuleTag::PaddingRight | StyleRuleTag::PaddingBottom |
+        StyleRuleTag::MarginLeft | StyleRuleTag::MarginTop | StyleRuleTag::MarginRight | StyleRuleTag::MarginBottom |
+        StyleRuleTag::BorderLeftWidth | StyleRuleTag::BorderTopWidth | StyleRuleTag::BorderRightWidth | StyleRuleTag::BorderBottomWidth => {
+            param_into_length(param)?
         },
-        StyleRuleTag::Padding | StyleRuleTag::Margin => {
-            params_into_offset(&rule.params)?
+        StyleRuleTag::Width | StyleRuleTag::Height | StyleRuleTag::MinWidth | StyleRuleTag::MinHeight => {
+            param_into_dimension(param, "auto")?
         },
-        StyleRuleTag::Border => {
-            StyleRule::Outline { size: rule.params.get(0)?.into_px()?, color: rule.params.get(1)?.into_color()?, }
+        StyleRuleTag::MaxWidth | StyleRuleTag::MaxHeight => {
+            param_into_dimension(param, "none")?
+        },
+        StyleRuleTag::BoxSizing => {
+            StyleRule::BoxSizing(match param.into_ident()? {
+                "content-box" => BoxSizing::ContentBox,
+                "border-box" => BoxSizing::BorderBox,
+                _ => None?
+            })
+        },
+        StyleRuleTag::Position => {
+            StyleRule::Position(match param.into_ident()? {
+                "static" => Position::Static,
+                "relative" => Position::Relative,
+                "absolute" => Position::Absolute,
+                "fixed" => Position::Fixed,
+                _ => None?
+            })
+        },
+        StyleRuleTag::Left | StyleRuleTag::Top | StyleRuleTag::Right | StyleRuleTag::Bottom => {
+            param_into_dimension(param, "auto")?
+        },
+        StyleRuleTag::ZIndex => StyleRule::ZIndex(match param.into_ident() {
+            Some("auto") => None,
+            _ => Some(param.into_number().filter(|e| e.fract() == 0.)? as i32)
+        }),
+        StyleRuleTag::WhiteSpace => {
+            StyleRule::WhiteSpace(match param.into_ident()? {
+                "normal" => WhiteSpace::Normal,
+                "nowrap" => WhiteSpace::NoWrap,
+                "pre" => WhiteSpace::Pre,
+                "pre-wrap" => WhiteSpace::PreWrap,
+                _ => None?
+            })
+        },
+        StyleRuleTag::WordBreak => {
+            StyleRule::WordBreak(match param.into_ident()? {
+                "normal" => WordBreak::Normal,
+                "break-all" => WordBreak::BreakAll,
+                "break-word" => WordBreak::BreakWord,
+                _ => None?
+            })
+        },
+        StyleRuleTag::FontWeight => StyleRule::FontWeight(match param.into_ident() {
+            Some("normal") | Some("lighter") => FontWeight::Normal,
+            Some("bold") | Some("bolder") => FontWeight::Bold,
+            Some(_) => None?,
+            // Numeric weights from 600 up are bold
+            None => if param.into_number().filter(|e| (1. ..=1000.).contains(e))? >= 600. { FontWeight::Bold } else { FontWeight::Normal }
+        }),
+        StyleRuleTag::FontStyle => {
+            StyleRule::FontStyle(match param.into_ident()? {
+                "normal" => FontStyle::Normal,
+                "italic" | "oblique" => FontStyle::Italic,
+                _ => None?
+            })
+        },
+        StyleRuleTag::FontSize => param_into_dimension(param, "medium")?,
+        StyleRuleTag::LetterSpacing => match param.into_ident() {
+            Some("normal") => StyleRule::Length(0.),
+            _ => param_into_length(param)?
+        },
+        StyleRuleTag::LineHeight => StyleRule::LineHeight(if let Some(px) = param.into_px() {
+            LineHeight::Px(px)
+        } else if let Some(percent) = param.into_percent() {
+            LineHeight::Scale(percent/100.)
+        } else if let Some(number) = param.into_number() {
+            LineHeight::Scale(number)
+        } else if param.into_ident()? == "normal" {
+            LineHeight::Normal
+        } else {
+            None?
+        }),
+        StyleRuleTag::OverflowX | StyleRuleTag::OverflowY => param_into_overflow(param)?,
+        StyleRuleTag::RowGap | StyleRuleTag::ColumnGap => param_into_length(param)?,
+        StyleRuleTag::FlexGrow | StyleRuleTag::FlexShrink => {
+            StyleRule::Number(param.into_number().filter(|e| *e >= 0.)?)
+        },
+        StyleRuleTag::FlexBasis => param_into_dimension(param, "auto")?,
+        StyleRuleTag::FlexDirection => {
+            StyleRule::FlexDirection(match param.into_ident()? {
+                "row" => FlexDirection::Row,
+                "row-reverse" => FlexDirection::RowReverse,
+                "column" => FlexDirection::Column,
+                "column-reverse" => FlexDirection::ColumnReverse,
+                _ => None?
+            })
+        },
+        StyleRuleTag::FlexWrap => {
+            StyleRule::FlexWrap(match param.into_ident()? {
+                "nowrap" => FlexWrap::NoWrap,
+                "wrap" => FlexWrap::Wrap,
+                _ => None?
+            })
+        },
+        StyleRuleTag::JustifyContent => {
+            StyleRule::JustifyContent(match param.into_ident()? {
+                "flex-start" | "start" => JustifyContent::FlexStart,
+                "flex-end" | "end" => JustifyContent::FlexEnd,
+                "center" => JustifyContent::Center,
+                "space-between" => JustifyContent::SpaceBetween,
+                "space-around" => JustifyContent::SpaceAround,
+                "space-evenly" => JustifyContent::SpaceEvenly,
+                _ => None?
+            })
+        },
+        StyleRuleTag::AlignItems => {
+            StyleRule::AlignItems(match param.into_ident()? {
+                "flex-start" | "start" => AlignItems::FlexStart,
+                "flex-end" | "end" => AlignItems::FlexEnd,
+                "center" => AlignItems::Center,
+                "stretch" => AlignItems::Stretch,
+                _ => None?
+            })
         },
         StyleRuleTag::Display => {
-            StyleRule::Display(match rule.params.get(0)?.into_ident()? {
+            StyleRule::Display(match param.into_ident()? {
                 "block" => DisplayType::Block,
                 "inline" => DisplayType::Inline,
+                "flex" => DisplayType::Flex,
+                "grid" => DisplayType::Grid,
                 _ => None?
             })
         },
         StyleRuleTag::Stretch => {
-            StyleRule::Stretch(match rule.params.get(0)?.into_ident()? {
+            StyleRule::Stretch(match param.into_ident()? {
                 "yes" | "true" => StretchType::True,
                 "false" | "no" => StretchType::False,
                 _ => None?
             })
         },
-        // _ => return None
-    }))
+        _ => return None
+    }))])
+}
+
+/// Fonts of the `@font-face` blocks that have both a family and a source
+pub fn generate_font_faces(css: &Css) -> Vec<FontFace> {
+    css.font_faces.iter().filter_map(|rules| {
+        let value = |name: &str| rules.iter().rev().find(|e| e.name == name).map(|e| e.params.as_slice());
+        let family = match params_into_families(value("font-family")?)?.as_slice() {
+            [family] => family.clone(),
+            _ => None?
+        };
+        let src = match value("src")? {
+            [src] => src.into_url().or(src.into_string())?.to_string(),
+            _ => None?
+        };
+        Some(FontFace { family, src })
+    }).collect()
 }
 
-pub fn generate_from(rules: &[CssRule]) -> Vec<(StyleRuleTag, StyleRule)> {
+pub fn generate_from(rules: &[CssRule]) -> Vec<(StyleRuleTag, Style)> {
     let mut styles = vec![];
     for rule in rules {
-        styles.push(match generate_rule(rule) {
+        styles.extend(match generate_rule(rule) {
             Some(x) => x,
             _ => continue
         });
     }
     styles
 }
+
+#[test]
+fn test_border_shorthand_resets_omitted_parts() {
+    use crate::ui::Item;
+    let css = CssParser::new(".a { border: 2px red; } .b { border: 2px red; border-style: solid; } .c { border: solid; }").parse().unwrap();
+
+    // Like in browsers the omitted parts take their initial values, without a style no border is drawn
+    let a = generate_from(&css.blocks[0].rules);
+    assert!(matches!(find(&a, StyleRuleTag::BorderTopWidth), Some(Style::Value(StyleRule::Length(w))) if w == 2.));
+    assert!(matches!(find(&a, StyleRuleTag::BorderTopStyle), Some(Style::Value(StyleRule::BorderStyle(BorderStyle::None)))));
+    let c = generate_from(&css.blocks[2].rules);
+    assert!(matches!(find(&c, StyleRuleTag::BorderLeftWidth), Some(Style::Value(StyleRule::Length(w))) if w == 0.));
+    assert!(matches!(find(&c, StyleRuleTag::BorderLeftStyle), Some(Style::Value(StyleRule::BorderStyle(BorderStyle::Solid)))));
+
+    // Only a border with a style takes room in the layout
+    let inset = |styles: Vec<(StyleRuleTag, Style)>| {
+        let ui = styles.into_iter().fold(Item::build(), |item, (tag, style)| item.style(tag, style)).into_ui();
+        ui.calculate_layout(None).iter().find(|e| e.for_id == ui.root).unwrap().rect.pos.x
+    };
+    assert_eq!(inset(a), 0.);
+    assert_eq!(inset(generate_from(&css.blocks[1].rules)), 2.);
+}
+
+/// The value a block ends up with for the property, later declarations override earlier ones
+#[cfg(test)]
+fn find(styles: &[(StyleRuleTag, Style)], tag: StyleRuleTag) -> Option<Style> {
+    styles.iter().rev().find(|e| e.0 == tag).map(|e| e.1.clone())
+}
+
+#[cfg(test)]
+fn length(style: Option<Style>) -> f32 {
+    match style { Some(Style::Value(StyleRule::Length(v))) => v, _ => panic!() }
+}
+
+#[test]
+fn test_box_shorthands() {
+    let css = CssParser::new(".a { border: 2px red; padding: 4px; padding-left: 1px; border-bottom: solid 1px #ccc; } \
+                              .b { border-left-width: 3px; border: 2px solid blue; border-width: 1px 5px; margin: inherit; }").parse().unwrap();
+    let border_style = |style| match style { Some(Style::Value(StyleRule::BorderStyle(v))) => v, _ => panic!() };
+
+    let a = generate_from(&css.blocks[0].rules);
+    assert_eq!(a.len(), 12 + 4 + 1 + 3);
+    assert_eq!(length(find(&a, StyleRuleTag::PaddingLeft)), 1.);
+    assert_eq!(length(find(&a, StyleRuleTag::PaddingRight)), 4.);
+    assert_eq!(length(find(&a, StyleRuleTag::BorderTopWidth)), 2.);
+    assert_eq!(length(find(&a, StyleRuleTag::BorderBottomWidth)), 1.);
+    assert_eq!(border_style(find(&a, StyleRuleTag::BorderBottomStyle)), BorderStyle::Solid);
+
+    let b = generate_from(&css.blocks[1].rules);
+    assert_eq!(length(find(&b, StyleRuleTag::BorderLeftWidth)), 1.);
+    assert_eq!(length(find(&b, StyleRuleTag::BorderTopWidth)), 5.);
+    assert!(matches!(find(&b, StyleRuleTag::BorderRightColor), Some(Style::Value(StyleRule::Color { color: (0, 0, 255, 255) }))));
+    assert!(StyleRuleTag::MARGIN.iter().all(|e| matches!(find(&b, *e), Some(Style::Inherit))));
+
+    // Invalid declarations are dropped as a whole
+    let css = CssParser::new(".c { border: 1px 2px; padding: 1px 2px 3px; border-top-style: dotted; }").parse().unwrap();
+    assert!(generate_from(&css.blocks[0].rules).is_empty());
+}
+
+#[test]
+fn test_size_properties() {
+    let css = CssParser::new(".a { width: 50%; min-height: 10px; max-width: none; box-sizing: border-box; } \
+                              .b { width: none; max-height: auto; box-sizing: padding-box; }").parse().unwrap();
+    let a = generate_from(&css.blocks[0].rules);
+    assert!(matches!(find(&a, StyleRuleTag::Width), Some(Style::Value(StyleRule::Dimension(Dimension::Percent(w)))) if w == 50.));
+    assert!(matches!(find(&a, StyleRuleTag::MinHeight), Some(Style::Value(StyleRule::Dimension(Dimension::Px(h)))) if h == 10.));
+    assert!(matches!(find(&a, StyleRuleTag::MaxWidth), Some(Style::Value(StyleRule::Dimension(Dimension::Auto)))));
+    assert!(matches!(find(&a, StyleRuleTag::BoxSizing), Some(Style::Value(StyleRule::BoxSizing(BoxSizing::BorderBox)))));
+    // `none` is only for maximums and `auto` only for the rest
+    assert!(generate_from(&css.blocks[1].rules).is_empty());
+}
+
+#[test]
+fn test_flex_shorthands() {
+    let css = CssParser::new(".d { flex: 2; gap: 4px 8px; } .e { flex: none; flex-basis: 50%; }").parse().unwrap();
+    let d = generate_from(&css.blocks[0].rules);
+    assert!(matches!(find(&d, StyleRuleTag::FlexGrow), Some(Style::Value(StyleRule::Number(g))) if g == 2.));
+    assert!(matches!(find(&d, StyleRuleTag::FlexBasis), Some(Style::Value(StyleRule::Dimension(Dimension::Px(b)))) if b == 0.));
+    assert_eq!(length(find(&d, StyleRuleTag::ColumnGap)), 8.);
+    let e = generate_from(&css.blocks[1].rules);
+    assert!(matches!(find(&e, StyleRuleTag::FlexShrink), Some(Style::Value(StyleRule::Number(s))) if s == 0.));
+    assert!(matches!(find(&e, StyleRuleTag::FlexBasis), Some(Style::Value(StyleRule::Dimension(Dimension::Percent(b)))) if b == 50.));
+}
+
+#[test]
+fn test_grid_tracks_and_areas() {
+    let css = CssParser::new(".f { grid-template-columns: 10px repeat(2, 1fr auto); grid-template-areas: 'a a' \"b .\"; grid-area: a; grid-row: 2 / span 3; } \
+                              .g { grid-template-areas: 'a' 'b c'; grid-column: span; }").parse().unwrap();
+    let f = generate_from(&css.blocks[0].rules);
+    assert!(matches!(find(&f, StyleRuleTag::GridTemplateColumns), Some(Style::Value(StyleRule::Tracks(t)))
+        if t == vec![TrackSize::Px(10.), TrackSize::Fr(1.), TrackSize::Auto, TrackSize::Fr(1.), TrackSize::Auto]));
+    assert!(matches!(find(&f, StyleRuleTag::GridTemplateAreas), Some(Style::Value(StyleRule::Areas(a))) if a[1] == vec!["b", "."]));
+    assert!(matches!(find(&f, StyleRuleTag::GridColumnEnd), Some(Style::Value(StyleRule::GridLine(GridLine::Area(a)))) if a == "a"));
+    assert!(matches!(find(&f, StyleRuleTag::GridRowStart), Some(Style::Value(StyleRule::GridLine(GridLine::Line(2))))));
+    assert!(matches!(find(&f, StyleRuleTag::GridRowEnd), Some(Style::Value(StyleRule::GridLine(GridLine::Span(3))))));
+    assert!(generate_from(&css.blocks[1].rules).is_empty());
+}
+
+#[test]
+fn test_position_properties() {
+    let css = CssParser::new(".h { position: absolute; inset: 0px 10%; bottom: auto; z-index: -2; } .i { z-index: 1.5; position: sticky; }").parse().unwrap();
+    let h = generate_from(&css.blocks[0].rules);
+    assert!(matches!(find(&h, StyleRuleTag::Position), Some(Style::Value(StyleRule::Position(Position::Absolute)))));
+    assert!(matches!(find(&h, StyleRuleTag::Top), Some(Style::Value(StyleRule::Dimension(Dimension::Percent(t)))) if t == 10.));
+    assert!(matches!(find(&h, StyleRuleTag::Bottom), Some(Style::Value(StyleRule::Dimension(Dimension::Auto)))));
+    assert!(matches!(find(&h, StyleRuleTag::ZIndex), Some(Style::Value(StyleRule::ZIndex(Some(-2))))));
+    assert!(generate_from(&css.blocks[1].rules).is_empty());
+}
+
+#[test]
+fn test_line_breaking_properties() {
+    let css = CssParser::new(".j { white-space: pre-wrap; word-break: break-all; } .k { white-space: pre-line; }").parse().unwrap();
+    let j = generate_from(&css.blocks[0].rules);
+    assert!(matches!(find(&j, StyleRuleTag::WhiteSpace), Some(Style::Value(StyleRule::WhiteSpace(WhiteSpace::PreWrap)))));
+    assert!(matches!(find(&j, StyleRuleTag::WordBreak), Some(Style::Value(StyleRule::WordBreak(WordBreak::BreakAll)))));
+    assert!(generate_from(&css.blocks[1].rules).is_empty());
+}
+
+#[test]
+fn test_font_faces_and_families() {
+    let css = CssParser::new("@font-face { font-family: Pixel Sans; src: url('pixel.fnt'); } @font-face { font-family: a, b; src: 'x.bdf'; } \
+                              .l { font-family: 'Pixel Sans', monospace; } .m { font-family: 'a',; }").parse().unwrap();
+    assert_eq!(generate_font_faces(&css), vec![FontFace { family: "Pixel Sans".to_string(), src: "pixel.fnt".to_string() }]);
+    let l = generate_from(&css.blocks[0].rules);
+    assert!(matches!(find(&l, StyleRuleTag::FontFamily), Some(Style::Value(StyleRule::FontFamily(f))) if f == vec!["Pixel Sans", "monospace"]));
+    assert!(generate_from(&css.blocks[1].rules).is_empty());
+}
+
+#[test]
+fn test_font_properties() {
+    let css = CssParser::new(".n { font-weight: 700; font-style: oblique; font-size: 200%; letter-spacing: 2px; line-height: 1.5; } \
+                              .o { font-weight: bold; font-size: medium; letter-spacing: normal; line-height: 20px; } .p { font-weight: 1200; font-style: bold; }").parse().unwrap();
+    let n = generate_from(&css.blocks[0].rules);
+    assert!(matches!(find(&n, StyleRuleTag::FontWeight), Some(Style::Value(StyleRule::FontWeight(FontWeight::Bold)))));
+    assert!(matches!(find(&n, StyleRuleTag::FontStyle), Some(Style::Value(StyleRule::FontStyle(FontStyle::Italic)))));
+    assert!(matches!(find(&n, StyleRuleTag::FontSize), Some(Style::Value(StyleRule::Dimension(Dimension::Percent(p)))) if p == 200.));
+    assert_eq!(length(find(&n, StyleRuleTag::LetterSpacing)), 2.);
+    assert!(matches!(find(&n, StyleRuleTag::LineHeight), Some(Style::Value(StyleRule::LineHeight(LineHeight::Scale(v)))) if v == 1.5));
+    let o = generate_from(&css.blocks[1].rules);
+    assert!(matches!(find(&o, StyleRuleTag::FontSize), Some(Style::Value(StyleRule::Dimension(Dimension::Auto)))));
+    assert_eq!(length(find(&o, StyleRuleTag::LetterSpacing)), 0.);
+    assert!(matches!(find(&o, StyleRuleTag::LineHeight), Some(Style::Value(StyleRule::LineHeight(LineHeight::Px(v)))) if v == 20.));
+    assert!(generate_from(&css.blocks[2].rules).is_empty());
+}
+
+#[test]
+fn test_overflow_shorthand() {
+    let css = CssParser::new(".q { overflow: hidden auto; overflow-x: scroll; } .r { overflow: auto; } .s { overflow: a b c; }").parse().unwrap();
+    let q = generate_from(&css.blocks[0].rules);
+    assert!(matches!(find(&q, StyleRuleTag::OverflowX), Some(Style::Value(StyleRule::Overflow(Overflow::Scroll)))));
+    assert!(matches!(find(&q, StyleRuleTag::OverflowY), Some(Style::Value(StyleRule::Overflow(Overflow::Auto)))));
+    let r = generate_from(&css.blocks[1].rules);
+    assert!(matches!(find(&r, StyleRuleTag::OverflowX), Some(Style::Value(StyleRule::Overflow(Overflow::Auto)))));
+    assert!(generate_from(&css.blocks[2].rules).is_empty());
+}
@@ -2,8 +2,11 @@ use std::collections::BTreeSet;
 
 use crate::{
     atoms::Id,
-    css_parser::{CssParser, CssSelectorAtom, CssSelectorMultiple},
-    dom_repr::DomSystem,
+    css_parser::{
+        CssError, CssNamespace, CssParser, CssSelectorAtom, CssSelectorComposite,
+        CssSelectorMultiple,
+    },
+    dom_repr::{DomSystem, IndexKey},
 };
 
 pub trait QuerySelectorExt {
@@ -12,14 +15,86 @@ pub trait QuerySelectorExt {
 
 impl QuerySelectorExt for DomSystem {
     fn query_selector(&self, selector: &str) -> BTreeSet<Id> {
-        if let Ok(selector) = CssParser::new(selector).parse_selector() {
-            match_selector_against_dom(&selector, self)
+        if let Ok(selector) = Selector::parse(selector) {
+            selector.query(self)
         } else {
             BTreeSet::new()
         }
     }
 }
 
+/// Number of ids, classes and type selectors, compared in that order
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Specificity(pub u32, pub u32, pub u32);
+
+impl CssSelectorComposite {
+    pub fn specificity(&self) -> Specificity {
+        let mut result = Specificity::default();
+        for atom in &self.sels {
+            match atom {
+                CssSelectorAtom::Id(_) => result.0 += 1,
+                CssSelectorAtom::Class(_) => result.1 += 1,
+                CssSelectorAtom::Tag(_) => result.2 += 1,
+                CssSelectorAtom::Namespace(_) | CssSelectorAtom::Universal => {}
+            }
+        }
+        result
+    }
+}
+
+/// A selector list parsed once, it can be matched against any number of documents
+#[derive(Debug, PartialEq)]
+pub struct Selector {
+    selector: CssSelectorMultiple,
+}
+
+impl From<CssSelectorMultiple> for Selector {
+    fn from(selector: CssSelectorMultiple) -> Self {
+        Self { selector }
+    }
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Selector, CssError> {
+        Ok(Self::from(CssParser::new(selector).parse_selector_only()?))
+    }
+
+    /// The comma separated parts of the selector
+    pub fn alternatives(&self) -> &[CssSelectorComposite] {
+        &self.selector.sels
+    }
+
+    /// The highest specificity among the alternatives
+    pub fn specificity(&self) -> Specificity {
+        self.alternatives()
+            .iter()
+            .map(|e| e.specificity())
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub fn matches(&self, against: &DomSystem, id: Id) -> bool {
+        self.matching_specificity(against, id).is_some()
+    }
+
+    /// The highest specificity among the alternatives that match, which is what the cascade needs
+    pub fn matching_specificity(&self, against: &DomSystem, id: Id) -> Option<Specificity> {
+        if !against.is_inclusive_descendant(id, against.root()) {
+            return None;
+        }
+        self.alternatives()
+            .iter()
+            .filter(|e| matches_composite(e, against, id))
+            .map(|e| e.specificity())
+            .max()
+    }
+
+    /// All the matching elements in the document
+    pub fn query(&self, against: &DomSystem) -> BTreeSet<Id> {
+        match_selector_against_dom(&self.selector, against)
+    }
+}
+
 // We want to match a query selector (valid css selector) against the DOM
 pub fn match_selector_against_dom(
     selector: &CssSelectorMultiple,
@@ -27,56 +102,59 @@ pub fn match_selector_against_dom(
 ) -> BTreeSet<Id> {
     let mut multiple_set: BTreeSet<Id> = BTreeSet::new();
 
-    // After connection
-    for multiple in &selector.sels {
-        let mut composite_subset: BTreeSet<Id> = against.compose_children(against.root()).unwrap().drain(..).collect();
-        composite_subset.insert(against.root());
-        for composite in &multiple.sels {
-            match composite {
-                CssSelectorAtom::Class(class_name) => {
-                    let elements = against
-                        .get_elements_by_class_name(against.root(), class_name)
-                        .unwrap();
-                    composite_subset = composite_subset
-                        .intersection(&elements.iter().cloned().collect())
-                        .cloned()
-                        .collect();
-                }
-                CssSelectorAtom::Tag(tag_name) => {
-                    let elements = against
-                        .get_elements_by_tag_name(against.root(), tag_name)
-                        .unwrap();
-                    composite_subset = composite_subset
-                        .intersection(&elements.iter().cloned().collect())
-                        .cloned()
-                        .collect();
-                }
-                CssSelectorAtom::Id(id) => {
-                    if let Some(element) = against.get_element_by_id(against.root(), id) {
-                        composite_subset = composite_subset
-                            .intersection(&[element].iter().cloned().collect())
-                            .cloned()
-                            .collect();
-                        assert_eq!(composite_subset.len(), 1);
-                        break;
-                    }
-                    else {
-                        composite_subset.clear();
-                        break;
-                    }
-                }
-                _ => {
-                    unimplemented!()
-                }
+    for composite in &selector.sels {
+        for candidate in candidates_for(composite, against) {
+            // Detached elements are in the index as well
+            if matches_composite(composite, against, candidate)
+                && against.is_inclusive_descendant(candidate, against.root())
+            {
+                multiple_set.insert(candidate);
             }
         }
-
-        multiple_set = multiple_set.union(&composite_subset).copied().collect();
     }
 
     multiple_set
 }
 
+// The most selective atom decides which index to take the candidates from
+fn candidates_for(composite: &CssSelectorComposite, against: &DomSystem) -> Vec<Id> {
+    let key = composite
+        .sels
+        .iter()
+        .find_map(|atom| match atom {
+            CssSelectorAtom::Id(id) => Some(IndexKey::Id(id)),
+            _ => None,
+        })
+        .or_else(|| {
+            composite.sels.iter().find_map(|atom| match atom {
+                CssSelectorAtom::Class(class_name) => Some(IndexKey::Class(class_name)),
+                CssSelectorAtom::Tag(tag_name) => Some(IndexKey::Tag(tag_name)),
+                _ => None,
+            })
+        })
+        .unwrap_or(IndexKey::All);
+    against.candidates(key)
+}
+
+/// Tests a single element against every atom of the composite selector
+pub fn matches_composite(composite: &CssSelectorComposite, against: &DomSystem, id: Id) -> bool {
+    let element = match against.get_element(id) {
+        Some(element) => element,
+        None => return false,
+    };
+    composite.sels.iter().all(|atom| match atom {
+        CssSelectorAtom::Class(class_name) => element.class_list.contains(class_name),
+        CssSelectorAtom::Tag(tag_name) => &element.tag == tag_name,
+        CssSelectorAtom::Id(ident) => element.id() == Some(ident.as_str()),
+        CssSelectorAtom::Namespace(CssNamespace::Any) => true,
+        CssSelectorAtom::Namespace(CssNamespace::None) => element.namespace.is_none(),
+        CssSelectorAtom::Namespace(CssNamespace::Named { uri, .. }) => {
+            element.namespace.as_ref() == Some(uri)
+        }
+        CssSelectorAtom::Universal => true,
+    })
+}
+
 #[test]
 fn query_selector_test() {
     let input = r#"<?xml version="1.0" encoding="utf-8"?>
@@ -114,3 +192,104 @@ fn query_selector_test() {
     dbg!(&elements);
     assert_eq!(elements.len(), 6);
 }
+
+#[test]
+fn namespaced_selector_test() {
+    let input = r#"<?xml version="1.0" encoding="utf-8"?>
+<Div xmlns:w="urn:widgets">
+    <w:Div class="a"/>
+    <Div class="a"/>
+    <w:Slider/>
+</Div>
+    "#;
+
+    let sys = DomSystem::from_xml(input).unwrap();
+    let css = CssParser::new(
+        "@namespace w url(urn:widgets); w|Div {} |Div {} *|Div {} w|* {} Div.a {} w|*.a {}",
+    )
+    .parse()
+    .unwrap();
+    let counts: Vec<usize> = css
+        .blocks
+        .iter()
+        .map(|b| b.selector.query(&sys).len())
+        .collect();
+    assert_eq!(counts, vec![1, 2, 3, 2, 2, 1]);
+}
+
+#[test]
+fn indexed_matching_follows_mutations() {
+    let input = r#"<Div><Div class="a"/><Div class="b"/><Span class="a"/></Div>"#;
+    let mut sys = DomSystem::from_xml(input).unwrap();
+    let root = sys.document_element().unwrap();
+    let children = sys.get_element(root).unwrap().children.clone();
+    assert_eq!(sys.query_selector(".a").len(), 2);
+    assert_eq!(sys.query_selector("Div.a").len(), 1);
+
+    sys.add_class(children[1], "a");
+    assert_eq!(sys.query_selector(".a").len(), 3);
+    sys.set_attribute(children[0], "class", "c");
+    assert_eq!(sys.query_selector(".a").len(), 2);
+    sys.remove(children[2]);
+    assert_eq!(sys.query_selector(".a").len(), 1);
+
+    // Changes done through a mutable borrow are picked up as well
+    sys.get_element_mut(children[0]).unwrap().class_list.insert("a".to_string());
+    assert_eq!(sys.query_selector(".a").len(), 2);
+    sys.take_mutations();
+    assert_eq!(sys.query_selector(".a").len(), 2);
+    assert_eq!(sys.query_selector("*").len(), 3);
+
+    // A fragment that failed to parse leaves nothing behind
+    assert!(sys.parse_fragment(r#"<Div class="a"><Div>"#, root).is_err());
+    assert_eq!(sys.query_selector(".a").len(), 2);
+    assert_eq!(sys.candidates(IndexKey::Class("a")).len(), 2);
+}
+
+#[test]
+fn compiled_selector_test() {
+    let selector = Selector::parse("Div.a, #x, Span").unwrap();
+    assert_eq!(selector.specificity(), Specificity(1, 0, 0));
+    assert!(Selector::parse("Div {").is_err());
+    assert!(Selector::parse("").is_err());
+
+    // The same selector works across documents
+    let first = DomSystem::from_xml(r#"<Div class="a"><Span id="x"/></Div>"#).unwrap();
+    let second = DomSystem::from_xml(r#"<Span><Div/></Span>"#).unwrap();
+    assert_eq!(selector.query(&first).len(), 2);
+    assert_eq!(selector.query(&second).len(), 1);
+
+    let root = first.document_element().unwrap();
+    let span = first.get_element(root).unwrap().children[0];
+    assert!(selector.matches(&first, root));
+    assert_eq!(selector.matching_specificity(&first, root), Some(Specificity(0, 1, 1)));
+    assert_eq!(selector.matching_specificity(&first, span), Some(Specificity(1, 0, 0)));
+    assert!(!selector.matches(&first, first.root()));
+    assert!(!Selector::parse(".b").unwrap().matches(&first, root));
+}
+
+#[test]
+fn id_compound_and_duplicate_id_test() {
+    let input = r#"<?xml version="1.0" encoding="utf-8"?>
+<root>
+    <div id="a" class="foo"/>
+    <div id="a"/>
+    <span id="a" class="foo bar"/>
+    <div id="b" class="foo"/>
+</root>
+    "#;
+
+    let sys = DomSystem::from_xml(input).unwrap();
+
+    // Every element carrying the id matches, not only the first one
+    assert_eq!(sys.query_selector("#a").len(), 3);
+    assert_eq!(sys.query_selector("#a, #b").len(), 4);
+
+    // Atoms after the id are applied as well
+    assert_eq!(sys.query_selector("#a.foo").len(), 2);
+    assert_eq!(sys.query_selector("#a.foo.bar").len(), 1);
+    assert_eq!(sys.query_selector("div#a.foo").len(), 1);
+    assert_eq!(sys.query_selector(".foo#a").len(), 2);
+    assert_eq!(sys.query_selector("#a#b").len(), 0);
+    assert_eq!(sys.query_selector("#missing.foo").len(), 0);
+}
@@ -1,20 +1,35 @@
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 
+use crate::css_matcher::Selector;
 
-#[derive(Debug)]
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CssNamespace {
+    /// `*|tag`, matches elements in any namespace
+    Any,
+    /// `|tag`, matches elements without a namespace
+    None,
+    /// `prefix|tag` where prefix was declared with `@namespace`, prefix is "" for the default namespace
+    Named { prefix: String, uri: String },
+}
+
+#[derive(Debug, PartialEq)]
 pub enum CssSelectorAtom {
     Id(String),
     Class(String),
     Tag(String),
+    Namespace(CssNamespace),
+    Universal,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct CssSelectorComposite {
     pub sels: Vec<CssSelectorAtom>
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct CssSelectorMultiple {
     pub sels: Vec<CssSelectorComposite>
 }
@@ -25,7 +40,13 @@ impl std::fmt::Display for CssSelectorAtom {
         match self {
             Class(s) => write!(fmt, ".{}", s),
             Id(s) => write!(fmt, "#{}", s),
-            Tag(s) => write!(fmt, "{}", s.clone())
+            Tag(s) => write!(fmt, "{}", s.clone()),
+            Namespace(CssNamespace::Any) => write!(fmt, "*|"),
+            Namespace(CssNamespace::None) => write!(fmt, "|"),
+            // Default namespace is implicit
+            Namespace(CssNamespace::Named { prefix, .. }) if prefix == "" => Ok(()),
+            Namespace(CssNamespace::Named { prefix, .. }) => write!(fmt, "{}|", prefix),
+            Universal => write!(fmt, "*"),
         }
     }
 }
@@ -36,16 +57,26 @@ impl CssSelectorAtom {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum CssUnit {
-    Px
+    Px,
+    Percent,
+    /// Fraction of the free space in a grid
+    Fr
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum CssRuleParam {
     Color(u8, u8, u8, u8),
     UnknownIdent(String),
-    Unit(f32, CssUnit)
+    Unit(f32, CssUnit),
+    Number(f32),
+    String(String),
+    Url(String),
+    Slash,
+    Comma,
+    /// Arguments are split by commas, like `repeat(2, 1fr 2fr)`
+    Function(String, Vec<Vec<CssRuleParam>>)
 }
 
 impl CssRuleParam {
@@ -64,28 +95,60 @@ impl CssRuleParam {
     }
 
     pub fn into_px(&self) -> Option<f32> {
-        if let CssRuleParam::Unit(f, _) = self {
+        if let CssRuleParam::Unit(f, CssUnit::Px) = self {
+            return Some(*f)
+        }
+        None
+    }
+
+    pub fn into_string(&self) -> Option<&str> {
+        if let CssRuleParam::String(str) = self {
+            return Some(str.as_str())
+        }
+        None
+    }
+
+    pub fn into_url(&self) -> Option<&str> {
+        if let CssRuleParam::Url(str) = self {
+            return Some(str.as_str())
+        }
+        None
+    }
+
+    pub fn into_number(&self) -> Option<f32> {
+        if let CssRuleParam::Number(f) = self {
+            return Some(*f)
+        }
+        None
+    }
+
+    pub fn into_percent(&self) -> Option<f32> {
+        if let CssRuleParam::Unit(f, CssUnit::Percent) = self {
             return Some(*f)
         }
         None
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct CssRule {
     pub name: String,
     pub params: Vec<CssRuleParam>
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct CssBlock {
-    pub selector: CssSelectorMultiple,
+    pub selector: Selector,
     pub rules: Vec<CssRule>
 }
 
 #[derive(Debug)]
 pub struct Css {
-    pub blocks: Vec<CssBlock>
+    pub blocks: Vec<CssBlock>,
+    /// Namespaces declared with `@namespace`, prefix to URI, default namespace is under ""
+    pub namespaces: BTreeMap<String, String>,
+    /// Declarations of every `@font-face` block
+    pub font_faces: Vec<Vec<CssRule>>
 }
 
 pub enum CssErrorKind {
@@ -96,7 +159,10 @@ pub enum CssErrorKind {
     UnexpectedIdent(String),
     InvalidRuleParameter,
     InvalidNumber,
-    HexColorLengthMayNotBe(usize)
+    HexColorLengthMayNotBe(usize),
+    UnknownNamespacePrefix(String),
+    UnknownAtRule(String),
+    UnexpectedCharacter(char)
 }
 
 impl Debug for CssErrorKind {
@@ -111,7 +177,10 @@ impl Debug for CssErrorKind {
             UnexpectedIdent(s) => format!("Unexpected identifier '{}'", s),
             HexColorLengthMayNotBe(count) => format!("Hexadecimal color may not be of length {}", count),
             InvalidRuleParameter => "Invalid rule parameter".to_string(),
-            InvalidNumber => "InvalidNumber".to_string()
+            InvalidNumber => "InvalidNumber".to_string(),
+            UnknownNamespacePrefix(s) => format!("Unknown namespace prefix '{}'", s),
+            UnknownAtRule(s) => format!("Unknown at-rule '@{}'", s),
+            UnexpectedCharacter(ch) => format!("Unexpected character '{}'", ch)
         })
     }
 }
@@ -131,7 +200,9 @@ pub struct CssParser<'a> {
     current: char,
     line: usize,
     col: usize,
-    eof: bool
+    eof: bool,
+    namespaces: BTreeMap<String, String>,
+    font_faces: Vec<Vec<CssRule>>
 }
 
 impl<'a> CssParser<'a> {
@@ -161,7 +232,8 @@ impl<'a> CssParser<'a> {
             self.skip();
         }
 
-        if self.peek() == '/' {
+        // A slash on its own is a value separator
+        if self.peek() == '/' && self.chars.as_str().starts_with('*') {
             self.skip();
             if self.peek() == '*' {
                 loop {
@@ -249,8 +321,58 @@ impl<'a> CssParser<'a> {
         Ok(selector)
     }
 
+    fn parse_ident_or_universal(&mut self) -> Result<String, CssError> {
+        if self.peek() == '*' {
+            self.next()?;
+            self.no_rubbish();
+            return Ok("*".to_string())
+        }
+        self.parse_ident()
+    }
+
+    fn resolve_namespace(&self, prefix: &str) -> Result<CssNamespace, CssError> {
+        match self.namespaces.get(prefix) {
+            Some(uri) => Ok(CssNamespace::Named { prefix: prefix.to_string(), uri: uri.clone() }),
+            None => Err(self.err(CssErrorKind::UnknownNamespacePrefix(prefix.to_string())))
+        }
+    }
+
+    // Parses `tag`, `*`, `ns|tag`, `*|tag`, `|tag` and `ns|*`, namespace goes first if there's any
+    fn parse_type_selector(&mut self) -> Result<Vec<CssSelectorAtom>, CssError> {
+        let mut name = if self.peek() == '|' { None } else { Some(self.parse_ident_or_universal()?) };
+        let namespace = if self.peek() == '|' {
+            self.skip_char('|')?;
+            let namespace = match name.as_deref() {
+                None => CssNamespace::None,
+                Some("*") => CssNamespace::Any,
+                Some(prefix) => self.resolve_namespace(prefix)?
+            };
+            name = Some(self.parse_ident_or_universal()?);
+            Some(namespace)
+        }
+        else {
+            // Without explicit prefix the default namespace applies, if there's one
+            self.resolve_namespace("").ok()
+        };
+
+        let mut selectors = vec![];
+        if let Some(namespace) = namespace {
+            selectors.push(CssSelectorAtom::Namespace(namespace));
+        }
+        selectors.push(match name.unwrap().as_str() {
+            "*" => CssSelectorAtom::Universal,
+            tag => CssSelectorAtom::Tag(tag.to_string())
+        });
+        Ok(selectors)
+    }
+
     fn parse_composite_selector(&mut self) -> Result<CssSelectorComposite, CssError> {
-        let mut selectors = vec![self.parse_selector_atomic()?];
+        let mut selectors = if self.peek() == '.' || self.peek() == '#' {
+            vec![self.parse_selector_atomic()?]
+        }
+        else {
+            self.parse_type_selector()?
+        };
         while self.peek() == '.' || self.peek() == '#' {
             selectors.push(self.parse_selector_atomic()?);
         }
@@ -271,6 +393,16 @@ impl<'a> CssParser<'a> {
         self.parse_multiple_selector()
     }
 
+    /// Same as `parse_selector`, but fails if there's anything after the selector
+    pub fn parse_selector_only(&mut self) -> Result<CssSelectorMultiple, CssError> {
+        let selector = self.parse_multiple_selector()?;
+        if !self.eof {
+            let ch = self.peek();
+            return Err(self.err(CssErrorKind::UnexpectedCharacter(ch)))
+        }
+        Ok(selector)
+    }
+
     fn skip_char(&mut self, ch: char) -> Result<(), CssError>{
         if self.peek() == ch {
             self.next()?;
@@ -312,17 +444,34 @@ impl<'a> CssParser<'a> {
 
     fn parse_unit_value(&mut self) -> Result<CssRuleParam, CssError> {
         let number = self.parse_number()?;
+        if self.peek() == '%' {
+            self.skip_char('%')?;
+            return Ok(CssRuleParam::Unit(number, CssUnit::Percent))
+        }
+        if !self.peek().is_alphabetic() {
+            return Ok(CssRuleParam::Number(number))
+        }
         let unit = self.parse_ident()?;
 
         if unit == "px" {
             return Ok(CssRuleParam::Unit(number, CssUnit::Px))
         }
+        if unit == "fr" {
+            return Ok(CssRuleParam::Unit(number, CssUnit::Fr))
+        }
         Err(self.err(CssErrorKind::UnknownUnit(unit)))
     }
 
     fn parse_ident_rule(&mut self) -> Result<CssRuleParam, CssError> {
         let ident = self.parse_ident()?;
 
+        if ident == "url" && self.peek() == '(' {
+            return Ok(CssRuleParam::Url(self.parse_url()?))
+        }
+        if self.peek() == '(' {
+            return self.parse_function(ident)
+        }
+
         // For now we will always assume it's a color
         Ok(match ident.as_str() {
             "red" => CssRuleParam::Color(255, 0, 0, 255),
@@ -336,6 +485,22 @@ impl<'a> CssParser<'a> {
         })
     }
 
+    fn parse_function(&mut self, name: String) -> Result<CssRuleParam, CssError> {
+        self.skip_char('(')?;
+        let mut args = vec![vec![]];
+        while self.peek() != ')' {
+            if self.peek() == ',' {
+                self.skip_char(',')?;
+                args.push(vec![]);
+                continue;
+            }
+            let param = self.parse_value()?;
+            args.last_mut().unwrap().push(param);
+        }
+        self.skip_char(')')?;
+        Ok(CssRuleParam::Function(name, args))
+    }
+
     fn parse_hex_color(&mut self) -> Result<CssRuleParam, CssError> {
         self.skip_char('#')?;
         let mut hex = "".to_string();
@@ -420,6 +585,17 @@ impl<'a> CssParser<'a> {
         else if self.peek() == '#' {
             self.parse_hex_color()
         }
+        else if self.peek() == '"' || self.peek() == '\'' {
+            Ok(CssRuleParam::String(self.parse_quoted()?))
+        }
+        else if self.peek() == '/' {
+            self.skip_char('/')?;
+            Ok(CssRuleParam::Slash)
+        }
+        else if self.peek() == ',' {
+            self.skip_char(',')?;
+            Ok(CssRuleParam::Comma)
+        }
         else {
             Err(self.err(CssErrorKind::InvalidRuleParameter))
         }
@@ -439,6 +615,81 @@ impl<'a> CssParser<'a> {
         Ok(rule)
     }
 
+    fn parse_quoted(&mut self) -> Result<String, CssError> {
+        let quote = self.next()?;
+        let mut res = "".to_string();
+        while self.peek() != quote {
+            res.push(self.next()?);
+        }
+        self.skip_char(quote)?;
+        Ok(res)
+    }
+
+    // Accepts `"uri"`, `url(uri)` and `url("uri")`
+    fn parse_uri(&mut self) -> Result<String, CssError> {
+        if self.peek() == '"' || self.peek() == '\'' {
+            return self.parse_quoted()
+        }
+        let ident = self.parse_ident()?;
+        if ident != "url" {
+            return Err(self.err(CssErrorKind::UnexpectedIdent(ident)))
+        }
+        self.parse_url()
+    }
+
+    // What follows `url`, the uri in parentheses, quoted or not
+    fn parse_url(&mut self) -> Result<String, CssError> {
+        // We don't use skip_char here since the uri itself may start with a slash
+        if self.peek() != '(' {
+            return Err(self.err(CssErrorKind::ExpectedCharacter('(')))
+        }
+        self.next()?;
+        let uri = if self.peek() == '"' || self.peek() == '\'' {
+            self.parse_quoted()?
+        }
+        else {
+            let mut res = "".to_string();
+            while self.peek() != ')' {
+                res.push(self.next()?);
+            }
+            res.trim().to_string()
+        };
+        self.skip_char(')')?;
+        Ok(uri)
+    }
+
+    // `@namespace prefix uri;` or `@namespace uri;` for the default namespace
+    fn parse_namespace_rule(&mut self) -> Result<(), CssError> {
+        let prefix = if self.peek() == '"' || self.peek() == '\'' {
+            "".to_string()
+        }
+        else {
+            let ident = self.parse_ident()?;
+            if ident == "url" && self.peek() == '(' {
+                return Err(self.err(CssErrorKind::UnexpectedIdent(ident)))
+            }
+            ident
+        };
+        let uri = self.parse_uri()?;
+        self.skip_char(';')?;
+        self.namespaces.insert(prefix, uri);
+        Ok(())
+    }
+
+    fn parse_at_rule(&mut self) -> Result<(), CssError> {
+        self.skip_char('@')?;
+        let name = self.parse_ident()?;
+        match name.as_str() {
+            "namespace" => self.parse_namespace_rule(),
+            "font-face" => {
+                let rules = self.parse_declarations()?;
+                self.font_faces.push(rules);
+                Ok(())
+            },
+            _ => Err(self.err(CssErrorKind::UnknownAtRule(name)))
+        }
+    }
+
     pub fn new(source: &'a str) -> Self {
         let chars = source.chars();
         let mut this = Self {
@@ -446,7 +697,9 @@ impl<'a> CssParser<'a> {
             chars: chars,
             line: 0,
             col: 0,
-            eof: false
+            eof: false,
+            namespaces: BTreeMap::new(),
+            font_faces: vec![]
         };
         // Before that, we need to remove trailing spaces
         this.no_rubbish();
@@ -454,20 +707,32 @@ impl<'a> CssParser<'a> {
     }
 
     pub fn parse(&mut self) -> Result<Css, CssError> {
-        let mut result = Css { blocks: vec![] };
+        let mut result = Css { blocks: vec![], namespaces: BTreeMap::new(), font_faces: vec![] };
         while !self.eof {
-            let selector = self.parse_multiple_selector()?;
-            // This is needed as we encounter spaces and other ignored characters
-            self.skip_char('{')?;
-            let mut rules = vec![];
-            while self.peek() != '}' {
-                rules.push(self.parse_rule()?);
+            if self.peek() == '@' {
+                self.parse_at_rule()?;
+                continue;
             }
-            self.skip_char('}')?;
-            result.blocks.push(CssBlock { rules, selector });
+            let selector = self.parse_multiple_selector()?;
+            let rules = self.parse_declarations()?;
+            result.blocks.push(CssBlock { rules, selector: Selector::from(selector) });
         }
+        result.namespaces = self.namespaces.clone();
+        result.font_faces = self.font_faces.drain(..).collect();
         Ok(result)
     }
+
+    // Rules between braces
+    fn parse_declarations(&mut self) -> Result<Vec<CssRule>, CssError> {
+        // This is needed as we encounter spaces and other ignored characters
+        self.skip_char('{')?;
+        let mut rules = vec![];
+        while self.peek() != '}' {
+            rules.push(self.parse_rule()?);
+        }
+        self.skip_char('}')?;
+        Ok(rules)
+    }
 }
 
 #[test]
@@ -543,3 +808,80 @@ fn test_identifier_parsing() {
     let error = parser.parse_ident().unwrap_err();
     assert!(matches!(error.kind, CssErrorKind::InvalidSymbolInIdent));
 }
+
+#[test]
+fn test_namespace_parsing() {
+    let mut parser = CssParser::new(r#"
+        @namespace w url(urn:widgets);
+        @namespace "urn:layout";
+        w|Slider, *|Div, |Span, w|* { color: red; }
+    "#);
+    let css = parser.parse().unwrap();
+    assert_eq!(css.namespaces.get("w").unwrap(), "urn:widgets");
+    assert_eq!(css.namespaces.get("").unwrap(), "urn:layout");
+
+    let sels = css.blocks[0].selector.alternatives();
+    let strings: Vec<String> = sels.iter()
+        .map(|c| c.sels.iter().map(|a| a.to_string()).collect())
+        .collect();
+    assert_eq!(strings, vec!["w|Slider", "*|Div", "|Span", "w|*"]);
+    assert!(matches!(&sels[0].sels[0], CssSelectorAtom::Namespace(CssNamespace::Named { uri, .. }) if uri == "urn:widgets"));
+
+    // Default namespace applies to type selectors only
+    let mut parser = CssParser::new("@namespace 'urn:layout'; Div.a, .b {}");
+    let css = parser.parse().unwrap();
+    let sels = css.blocks[0].selector.alternatives();
+    assert!(matches!(&sels[0].sels[0], CssSelectorAtom::Namespace(CssNamespace::Named { uri, .. }) if uri == "urn:layout"));
+    assert!(matches!(&sels[1].sels[0], CssSelectorAtom::Class(_)));
+
+    let error = CssParser::new("x|Div {}").parse().unwrap_err();
+    assert!(matches!(error.kind, CssErrorKind::UnknownNamespacePrefix(_)));
+}
+
+#[test]
+fn test_unit_values() {
+    let mut parser = CssParser::new("width: 50%; height: 10px;");
+    let rule = parser.parse_rule().unwrap();
+    assert_eq!(rule.params, vec![CssRuleParam::Unit(50., CssUnit::Percent)]);
+    assert_eq!(rule.params[0].into_px(), None);
+    assert_eq!(rule.params[0].into_percent(), Some(50.));
+    let rule = parser.parse_rule().unwrap();
+    assert_eq!(rule.params[0].into_percent(), None);
+
+    let mut parser = CssParser::new("flex: 1 0.5 10px;");
+    let rule = parser.parse_rule().unwrap();
+    assert_eq!(rule.params, vec![CssRuleParam::Number(1.), CssRuleParam::Number(0.5), CssRuleParam::Unit(10., CssUnit::Px)]);
+}
+
+#[test]
+fn test_grid_values() {
+    let mut parser = CssParser::new("grid-template-columns: 100px repeat(2, 1fr auto); grid-column: 1/span 2; grid-template-areas: \"a b\" 'c d'; /* x */ a: 1;");
+    let rule = parser.parse_rule().unwrap();
+    assert_eq!(rule.params, vec![
+        CssRuleParam::Unit(100., CssUnit::Px),
+        CssRuleParam::Function("repeat".to_string(), vec![
+            vec![CssRuleParam::Number(2.)],
+            vec![CssRuleParam::Unit(1., CssUnit::Fr), CssRuleParam::UnknownIdent("auto".to_string())]
+        ])
+    ]);
+    let rule = parser.parse_rule().unwrap();
+    assert_eq!(rule.params, vec![CssRuleParam::Number(1.), CssRuleParam::Slash, CssRuleParam::UnknownIdent("span".to_string()), CssRuleParam::Number(2.)]);
+    let rule = parser.parse_rule().unwrap();
+    assert_eq!(rule.params, vec![CssRuleParam::String("a b".to_string()), CssRuleParam::String("c d".to_string())]);
+    // Comments are still skipped
+    assert_eq!(parser.parse_rule().unwrap().name, "a");
+}
+
+#[test]
+fn test_font_face() {
+    let css = CssParser::new("@font-face { font-family: \"Pixel Sans\"; src: url(fonts/pixel.fnt); } \
+                              .a { font-family: \"Pixel Sans\", Fixed Mono, monospace; }").parse().unwrap();
+    assert_eq!(css.font_faces.len(), 1);
+    assert_eq!(css.font_faces[0][1].params, vec![CssRuleParam::Url("fonts/pixel.fnt".to_string())]);
+    assert_eq!(css.blocks.len(), 1);
+    assert_eq!(css.blocks[0].rules[0].params, vec![
+        CssRuleParam::String("Pixel Sans".to_string()), CssRuleParam::Comma,
+        CssRuleParam::UnknownIdent("Fixed".to_string()), CssRuleParam::UnknownIdent("Mono".to_string()), CssRuleParam::Comma,
+        CssRuleParam::UnknownIdent("monospace".to_string())
+    ]);
+}
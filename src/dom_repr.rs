@@ -1,94 +1,356 @@
-use crate::bucket_array::{self, BucketArray};
+use crate::bucket_array::BucketArray;
 use crate::util::first_until_whitespace;
 use crate::util::split_by_whitespace_trimmed;
 use crate::Id;
-use std::collections::{hash_map::HashMap, BTreeSet};
+use std::collections::{hash_map::HashMap, BTreeMap, BTreeSet};
+use xml::name::OwnedName;
+use xml::namespace::{NS_XMLNS_PREFIX, NS_XML_PREFIX};
+use xml::common::Position;
 use xml::reader::XmlEvent;
 use xml::EventReader;
 
+/// Location in the source document, both counting from 0
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SourcePos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl SourcePos {
+    fn from_xml(pos: xml::common::TextPosition) -> Self {
+        Self {
+            line: pos.row as usize,
+            col: pos.column as usize,
+        }
+    }
+}
+
+pub struct XmlError {
+    pub pos: SourcePos,
+    pub message: String,
+    /// The line of the document where the error occured
+    pub context: String,
+}
+
+impl XmlError {
+    fn new(message: &str, pos: SourcePos, source: &str) -> Self {
+        Self {
+            pos,
+            message: message.to_string(),
+            // Tabs would misalign the caret
+            context: source
+                .lines()
+                .nth(pos.line)
+                .unwrap_or("")
+                .replace('\t', " "),
+        }
+    }
+}
+
+impl std::fmt::Display for XmlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "XML error: {} at {}:{}",
+            self.message,
+            self.pos.line + 1,
+            self.pos.col + 1
+        )?;
+        writeln!(f, "{}", self.context)?;
+        write!(f, "{}^", " ".repeat(self.pos.col))
+    }
+}
+
+impl std::fmt::Debug for XmlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// A namespace-qualified name, prefixes are resolved while parsing so only the URI is kept
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct QualName {
+    pub namespace: Option<String>,
+    pub local: String,
+}
+
+impl QualName {
+    pub fn new(namespace: Option<&str>, local: &str) -> Self {
+        Self {
+            namespace: namespace.map(|e| e.to_string()),
+            local: local.to_string(),
+        }
+    }
+
+    fn from_xml(name: &OwnedName) -> Self {
+        Self {
+            // xml-rs reports "no namespace" as an empty URI in some cases
+            namespace: name.namespace.clone().filter(|e| e != ""),
+            local: name.local_name.clone(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Element {
+    /// Local name of the element, without the prefix
     pub tag: String,
-    attrs: HashMap<String, String>,
+    pub namespace: Option<String>,
+    /// Prefix to namespace URI mappings in scope of this element, default namespace is under ""
+    pub namespaces: BTreeMap<String, String>,
+    attrs: HashMap<QualName, String>,
     pub class_list: BTreeSet<String>,
     id: Option<String>,
     pub children: Vec<Id>,
     parent: Option<Id>,
+    pub pos: SourcePos,
+}
+
+impl Element {
+    /// Gets an attribute that has no namespace
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attr_ns(None, name)
+    }
+
+    pub fn attr_ns(&self, namespace: Option<&str>, name: &str) -> Option<&str> {
+        self.attrs
+            .get(&QualName::new(namespace, name))
+            .map(|e| e.as_str())
+    }
+
+    pub fn attrs(&self) -> impl Iterator<Item = (&QualName, &str)> {
+        self.attrs.iter().map(|(k, v)| (k, v.as_str()))
+    }
+
+    pub fn lookup_namespace_uri(&self, prefix: &str) -> Option<&str> {
+        self.namespaces.get(prefix).map(|e| e.as_str())
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
 }
 
 #[derive(Debug)]
 pub struct Text {
     pub text: String,
+    parent: Option<Id>,
+    pub pos: SourcePos,
+}
+
+/// Holds the top-level nodes, there may be any number of them including text
+#[derive(Debug)]
+pub struct Document {
+    pub children: Vec<Id>,
 }
 
 #[derive(Debug)]
 pub enum DomComponent {
     Text(Text),
     Element(Element),
+    Document(Document),
 }
 
+// xml-rs only accepts a single root element, so the markup is wrapped into this one
+const FRAGMENT_TAG: &str = "domlet-fragment";
+
 struct XmlToDom<'a> {
     reader: EventReader<&'a [u8]>,
+    /// The markup before wrapping, used for error context
+    source: &'a str,
+    /// Where the wrapper start tag was inserted and how long it is, positions after it are shifted back
+    inserted_at: SourcePos,
+    inserted_len: usize,
+    /// Every node inserted so far, so they can be dropped if parsing fails
+    created: Vec<Id>,
 }
 
 impl<'a> XmlToDom<'a> {
-    pub fn new(bytes: &'a [u8]) -> Self {
-        Self {
-            reader: EventReader::new(bytes),
+    /// Length of the prolog: the XML declaration together with the doctype, comments and processing
+    /// instructions that may follow it. None of them are allowed inside of an element
+    fn prolog_len(source: &str) -> usize {
+        let mut len = 0;
+        loop {
+            let rest = &source[len..];
+            let item = rest.trim_start();
+            let end = if item.starts_with("<!--") {
+                item.find("-->").map(|e| e + 3)
+            } else if item.starts_with("<?") {
+                item.find("?>").map(|e| e + 2)
+            } else if item.starts_with("<!DOCTYPE") {
+                // The internal subset in brackets can contain `>` as well
+                match (item.find('['), item.find('>')) {
+                    (Some(open), Some(close)) if open < close => item[open..]
+                        .find(']')
+                        .and_then(|e| item[open + e..].find('>').map(|c| open + e + c + 1)),
+                    (_, close) => close.map(|e| e + 1),
+                }
+            } else {
+                None
+            };
+            match end {
+                Some(end) => len += rest.len() - item.len() + end,
+                None => return len,
+            }
         }
     }
 
-    pub fn parse(&mut self) -> Result<DomSystem, xml::reader::Error> {
-        let mut sys = DomSystem {
-            elements: BucketArray::new(),
-            root: 0,
+    /// Wraps `source` into the fragment tag, keeping the prolog in front of it
+    pub fn wrap(source: &str, namespaces: &BTreeMap<String, String>) -> (String, SourcePos, usize) {
+        let (declaration, body) = source.split_at(Self::prolog_len(source));
+        let mut open = format!("<{}", FRAGMENT_TAG);
+        for (prefix, uri) in namespaces {
+            let uri = uri.replace('&', "&amp;").replace('"', "&quot;");
+            if prefix.is_empty() {
+                open.push_str(&format!(" xmlns=\"{}\"", uri));
+            } else {
+                open.push_str(&format!(" xmlns:{}=\"{}\"", prefix, uri));
+            }
+        }
+        open.push('>');
+
+        let inserted_at = SourcePos {
+            line: declaration.matches('\n').count(),
+            col: declaration
+                .rsplit('\n')
+                .next()
+                .map(|e| e.chars().count())
+                .unwrap_or(0),
         };
-        let res = self.parse_elements(&mut sys, None)?;
-        self.post_process_attributes(&mut sys);
-        assert_eq!(res.len(), 1);
-        sys.root = *res.get(0).unwrap();
-        Ok(sys)
+        let wrapped = format!("{}{}{}</{}>", declaration, open, body, FRAGMENT_TAG);
+        (wrapped, inserted_at, open.chars().count())
     }
 
-    fn post_process_attributes(&mut self, sys: &mut DomSystem) {
-        for (_, element) in sys.elements.iter_mut() {
-            // We only want to do that for elements, not text
-            if let DomComponent::Element(el) = element {
-                // Here we will need to assign class list to our elements
-                let classes = el.attrs.remove("class").unwrap_or("".to_string());
-                let id = el.attrs.remove("id").unwrap_or("".to_string());
-                el.class_list = split_by_whitespace_trimmed(&classes).drain(..).collect();
-                let id_value = first_until_whitespace(&id);
-                // If id is "" there's no id
-                el.id = if id_value == "" { None } else { Some(id_value) }
+    pub fn new(wrapped: &'a str, source: &'a str, inserted_at: SourcePos, inserted_len: usize) -> Self {
+        Self {
+            reader: EventReader::new(wrapped.as_bytes()),
+            source,
+            inserted_at,
+            inserted_len,
+            created: vec![],
+        }
+    }
+
+    // Moves positions that come after the wrapper start tag back to where they are in the source
+    fn unshift(&self, mut pos: SourcePos) -> SourcePos {
+        if pos.line == self.inserted_at.line && pos.col >= self.inserted_at.col {
+            pos.col = (pos.col - self.inserted_at.col).saturating_sub(self.inserted_len)
+                + self.inserted_at.col;
+        }
+        pos
+    }
+
+    fn position(&self) -> SourcePos {
+        self.unshift(SourcePos::from_xml(self.reader.position()))
+    }
+
+    /// Where the tag the reader has just read starts. The reader can report the first tag of a document
+    /// past its `<` because it has to look ahead for the XML declaration, so the position is walked back
+    fn tag_start(&self, pos: SourcePos) -> SourcePos {
+        let line: Vec<char> = self.source.lines().nth(pos.line).unwrap_or("").chars().collect();
+        let col = line[..(pos.col + 1).min(line.len())]
+            .iter()
+            .rposition(|c| *c == '<')
+            .unwrap_or(pos.col);
+        SourcePos { line: pos.line, col }
+    }
+
+    fn error(&self, error: &xml::reader::Error) -> XmlError {
+        let pos = self.unshift(SourcePos::from_xml(error.position()));
+        XmlError::new(error.msg(), pos, self.source)
+    }
+
+    /// Parses everything inside of the wrapper and attaches the nodes to `parent`
+    pub fn parse_into(&mut self, sys: &mut DomSystem, parent: Id) -> Result<Vec<Id>, XmlError> {
+        // Skip everything until the wrapper opens
+        loop {
+            match self.reader.next() {
+                Ok(XmlEvent::StartElement { .. }) => break,
+                Err(e) => return Err(self.error(&e)),
+                _ => {}
             }
         }
+        let ids = self.parse_elements(sys, Some(parent))?;
+        // The document has to be read till the end, to check that it's well-formed
+        loop {
+            match self.reader.next() {
+                Ok(XmlEvent::EndDocument) => break,
+                Err(e) => return Err(self.error(&e)),
+                _ => {}
+            }
+        }
+        Ok(ids)
+    }
+
+    pub fn parse(&mut self) -> Result<DomSystem, XmlError> {
+        let mut elements = BucketArray::new();
+        let root = elements.insert(DomComponent::Document(Document { children: vec![] }));
+        let mut sys = DomSystem {
+            elements,
+            root,
+            mutations: vec![],
+            index: DomIndex::default(),
+        };
+        let res = self.parse_into(&mut sys, root)?;
+        if let Some(DomComponent::Document(document)) = sys.elements.get_mut(sys.root) {
+            document.children = res;
+        }
+        Ok(sys)
     }
 
     fn parse_element_open(
         &mut self,
-        name: String,
+        name: &OwnedName,
         attributes: &[xml::attribute::OwnedAttribute],
+        namespace: &xml::namespace::Namespace,
+        pos: SourcePos,
         sys: &mut DomSystem,
         parent: Option<Id>,
-    ) -> Result<Id, xml::reader::Error> {
+    ) -> Result<Id, XmlError> {
+        let name = QualName::from_xml(name);
+        let mut attrs = attributes
+            .iter()
+            .map(|a| (QualName::from_xml(&a.name), a.value.to_string()))
+            .collect::<HashMap<QualName, String>>();
+        // Class list and id are kept separately from the rest of the attributes
+        let classes = attrs
+            .remove(&QualName::new(None, "class"))
+            .unwrap_or("".to_string());
+        let id = attrs
+            .remove(&QualName::new(None, "id"))
+            .unwrap_or("".to_string());
+        let id_value = first_until_whitespace(&id);
+
         let el = Element {
-            tag: name,
-            attrs: attributes
+            tag: name.local,
+            namespace: name.namespace,
+            namespaces: namespace
+                .0
                 .iter()
-                .map(|a| (a.name.to_string(), a.value.to_string()))
-                .collect::<HashMap<String, String>>(),
-
-            // We well assign those later
-            class_list: BTreeSet::new(),
-            id: None,
+                // Builtin prefixes are always there, so they're not worth keeping
+                .filter(|(prefix, uri)| {
+                    prefix.as_str() != NS_XML_PREFIX
+                        && prefix.as_str() != NS_XMLNS_PREFIX
+                        && uri.as_str() != ""
+                })
+                .map(|(prefix, uri)| (prefix.clone(), uri.clone()))
+                .collect(),
+            attrs,
+            class_list: split_by_whitespace_trimmed(&classes).drain(..).collect(),
+            // If id is "" there's no id
+            id: if id_value.is_empty() { None } else { Some(id_value) },
 
             children: vec![],
             parent,
+            pos,
         };
 
         // This is the id for current element to identify the chilren
         let current_id = sys.elements.insert(DomComponent::Element(el));
+        self.created.push(current_id);
+        if let Some(DomComponent::Element(el)) = sys.elements.get(current_id) {
+            sys.index.add(current_id, el);
+        }
         let children = self.parse_elements(sys, Some(current_id))?;
         // We need to get the element back since it's moved
         let el = sys.elements.get_mut(current_id).unwrap();
@@ -104,54 +366,427 @@ impl<'a> XmlToDom<'a> {
         &mut self,
         sys: &mut DomSystem,
         parent: Option<Id>,
-    ) -> Result<Vec<Id>, xml::reader::Error> {
+    ) -> Result<Vec<Id>, XmlError> {
         let mut ids = Vec::new();
         loop {
-            match self.reader.next() {
+            let event = self.reader.next();
+            // Position of the event we've just read
+            let pos = self.position();
+            match event {
                 Ok(XmlEvent::StartElement {
-                    name, attributes, ..
+                    name,
+                    attributes,
+                    namespace,
                 }) => {
                     ids.push(self.parse_element_open(
-                        name.to_string(),
+                        &name,
                         attributes.as_slice(),
+                        &namespace,
+                        self.tag_start(pos),
                         sys,
                         parent,
                     )?);
                 }
                 Ok(XmlEvent::EndElement { .. }) | Ok(XmlEvent::EndDocument) => return Ok(ids),
                 Ok(XmlEvent::Characters(text)) => {
-                    let text = Text { text };
+                    let text = Text { text, parent, pos };
                     let id = sys.elements.insert(DomComponent::Text(text));
+                    self.created.push(id);
                     ids.push(id);
                 }
-                Err(e) => return Err(e),
+                Err(e) => return Err(self.error(&e)),
                 _ => {}
             }
         }
     }
 }
+/// Describes a single change done to the DOM, class list and id changes are reported as attribute changes
+#[derive(Debug, Clone, PartialEq)]
+pub enum MutationRecord {
+    ChildList {
+        target: Id,
+        added: Vec<Id>,
+        /// These ids are no longer valid
+        removed: Vec<Id>,
+    },
+    Attribute {
+        target: Id,
+        name: QualName,
+        old_value: Option<String>,
+    },
+    Text {
+        target: Id,
+        old_value: String,
+    },
+    /// The node was borrowed mutably, so anything about it might have changed
+    Unknown { target: Id },
+}
+
+impl MutationRecord {
+    /// The node whose subtree is affected by the change
+    pub fn target(&self) -> Id {
+        match self {
+            MutationRecord::ChildList { target, .. }
+            | MutationRecord::Attribute { target, .. }
+            | MutationRecord::Text { target, .. }
+            | MutationRecord::Unknown { target } => *target,
+        }
+    }
+}
+
+/// What to look up in the element indexes
+#[derive(Debug, Clone, Copy)]
+pub enum IndexKey<'a> {
+    Id(&'a str),
+    Class(&'a str),
+    /// Local name of the element
+    Tag(&'a str),
+    All,
+}
+
+/// Lookup tables from ids, classes and tags to elements
+#[derive(Debug, Default)]
+struct DomIndex {
+    ids: HashMap<String, BTreeSet<Id>>,
+    classes: HashMap<String, BTreeSet<Id>>,
+    tags: HashMap<String, BTreeSet<Id>>,
+    /// Elements handed out mutably, anything about them might have changed so
+    /// they're always candidates until they get indexed again on the next change
+    loose: BTreeSet<Id>,
+}
+
+impl DomIndex {
+    fn add(&mut self, id: Id, el: &Element) {
+        if let Some(ident) = &el.id {
+            self.ids.entry(ident.clone()).or_default().insert(id);
+        }
+        for class in el.class_list.iter() {
+            self.classes.entry(class.clone()).or_default().insert(id);
+        }
+        self.tags.entry(el.tag.clone()).or_default().insert(id);
+    }
+
+    fn forget(&mut self, id: Id, el: &Element) {
+        fn forget_in(map: &mut HashMap<String, BTreeSet<Id>>, key: &str, id: Id) {
+            if let Some(set) = map.get_mut(key) {
+                set.remove(&id);
+                if set.is_empty() {
+                    map.remove(key);
+                }
+            }
+        }
+        if let Some(ident) = &el.id {
+            forget_in(&mut self.ids, ident, id);
+        }
+        for class in el.class_list.iter() {
+            forget_in(&mut self.classes, class, id);
+        }
+        forget_in(&mut self.tags, &el.tag, id);
+        self.loose.remove(&id);
+    }
+}
+
 #[derive(Debug)]
 pub struct DomSystem {
     elements: BucketArray<DomComponent>,
     root: Id,
+    mutations: Vec<MutationRecord>,
+    index: DomIndex,
 }
 
 impl DomSystem {
-    pub fn from_xml(xml: &str) -> Result<Self, xml::reader::Error> {
-        let bytes = xml.as_bytes();
-        XmlToDom::new(bytes).parse()
+    /// Multiple top-level nodes, text among them and empty documents are all accepted
+    pub fn from_xml(xml: &str) -> Result<Self, XmlError> {
+        let (wrapped, inserted_at, inserted_len) = XmlToDom::wrap(xml, &BTreeMap::new());
+        XmlToDom::new(&wrapped, xml, inserted_at, inserted_len).parse()
     }
 
+    /// Parses markup and appends the resulting nodes to the children of `parent`,
+    /// `parent` has to be an element or the document. Namespace prefixes in scope of `parent` can be used.
+    /// Returns ids of the new top-level nodes
+    pub fn parse_fragment(&mut self, xml: &str, parent: Id) -> Result<Vec<Id>, XmlError> {
+        let namespaces = match self.elements.get(parent) {
+            Some(DomComponent::Element(e)) => e.namespaces.clone(),
+            Some(DomComponent::Document(_)) => BTreeMap::new(),
+            _ => {
+                return Err(XmlError::new(
+                    "Fragment parent is not an element",
+                    SourcePos { line: 0, col: 0 },
+                    xml,
+                ))
+            }
+        };
+        self.flush_loose();
+        let (wrapped, inserted_at, inserted_len) = XmlToDom::wrap(xml, &namespaces);
+        let mut parser = XmlToDom::new(&wrapped, xml, inserted_at, inserted_len);
+        let ids = match parser.parse_into(self, parent) {
+            Ok(ids) => ids,
+            Err(e) => {
+                // Nothing is attached yet, so these are unreachable
+                for id in parser.created.iter().rev() {
+                    if let Some(DomComponent::Element(el)) = self.elements.get(*id) {
+                        self.index.forget(*id, el);
+                    }
+                    self.elements.remove(*id);
+                }
+                return Err(e);
+            }
+        };
+        match self.elements.get_mut(parent) {
+            Some(DomComponent::Element(e)) => e.children.extend(ids.iter().copied()),
+            Some(DomComponent::Document(d)) => d.children.extend(ids.iter().copied()),
+            _ => unreachable!(),
+        }
+        self.mutations.push(MutationRecord::ChildList {
+            target: parent,
+            added: ids.clone(),
+            removed: vec![],
+        });
+        Ok(ids)
+    }
+
+    /// Returns records of all the changes since the last call, oldest first
+    pub fn take_mutations(&mut self) -> Vec<MutationRecord> {
+        self.flush_loose();
+        self.mutations.drain(..).collect()
+    }
+
+    // Indexes the elements that were handed out mutably, their borrows have ended by now
+    fn flush_loose(&mut self) {
+        let loose: Vec<Id> = self.index.loose.iter().copied().collect();
+        for id in loose {
+            self.index.loose.remove(&id);
+            if let Some(DomComponent::Element(el)) = self.elements.get(id) {
+                self.index.add(id, el);
+            }
+        }
+    }
+
+    // Takes the element out of the indexes until the next change
+    fn loosen(&mut self, origin: Id) {
+        self.flush_loose();
+        if let Some(DomComponent::Element(el)) = self.elements.get(origin) {
+            self.index.forget(origin, el);
+            self.index.loose.insert(origin);
+        }
+    }
+
+    /// Elements that may match the key, the result has to be tested against the elements themselves.
+    /// Includes elements that aren't attached to the document
+    pub fn candidates(&self, key: IndexKey) -> Vec<Id> {
+        let indexed = match key {
+            IndexKey::Id(ident) => self.index.ids.get(ident),
+            IndexKey::Class(class_name) => self.index.classes.get(class_name),
+            IndexKey::Tag(tagname) => self.index.tags.get(tagname),
+            IndexKey::All => {
+                return self
+                    .elements
+                    .iter()
+                    .filter(|(_, e)| matches!(e, DomComponent::Element(_)))
+                    .map(|(id, _)| id)
+                    .collect()
+            }
+        };
+        let mut result: BTreeSet<Id> = indexed.cloned().unwrap_or_default();
+        result.extend(self.index.loose.iter().copied());
+        result.into_iter().collect()
+    }
+
+    /// True if `origin` is `ancestor` or is inside of it
+    pub fn is_inclusive_descendant(&self, origin: Id, ancestor: Id) -> bool {
+        let mut current = Some(origin);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            current = self.parent(id);
+        }
+        false
+    }
+
+    // Candidates from the index that pass the filter and are within origin, including origin itself,
+    // in document order
+    fn indexed_within<F: Fn(&Element) -> bool>(
+        &self,
+        origin: Id,
+        key: IndexKey,
+        filter: F,
+    ) -> Option<Vec<Id>> {
+        // Text or missing origin has nothing to look in
+        self.firstlevel_components(origin)?;
+        let mut result: Vec<Id> = self
+            .candidates(key)
+            .into_iter()
+            .filter(|id| self.get_element(*id).map(&filter).unwrap_or(false))
+            .filter(|id| self.is_inclusive_descendant(*id, origin))
+            .collect();
+        // Handles follow the order the elements were created in, which isn't the one of the document
+        result.sort_by_cached_key(|id| self.tree_path(*id));
+        Some(result)
+    }
+
+    pub fn parent(&self, origin: Id) -> Option<Id> {
+        match self.elements.get(origin)? {
+            DomComponent::Element(e) => e.parent,
+            DomComponent::Text(t) => t.parent,
+            DomComponent::Document(_) => None,
+        }
+    }
+
+    /// Detaches the node from its parent and drops it together with its subtree.
+    /// Returns false if there's no such node or it's the document
+    pub fn remove(&mut self, origin: Id) -> bool {
+        let parent = match self.parent(origin) {
+            Some(parent) => parent,
+            None => return false,
+        };
+        self.flush_loose();
+        match self.elements.get_mut(parent) {
+            Some(DomComponent::Element(e)) => e.children.retain(|e| *e != origin),
+            Some(DomComponent::Document(d)) => d.children.retain(|e| *e != origin),
+            _ => unreachable!(),
+        }
+        self.remove_rec(origin);
+        self.mutations.push(MutationRecord::ChildList {
+            target: parent,
+            added: vec![],
+            removed: vec![origin],
+        });
+        true
+    }
+
+    fn remove_rec(&mut self, origin: Id) {
+        if let Some(children) = self.firstlevel_components(origin).cloned() {
+            for child in children {
+                self.remove_rec(child);
+            }
+        }
+        if let DomComponent::Element(el) = self.elements.remove(origin) {
+            self.index.forget(origin, &el);
+        }
+    }
+
+    /// `class` and `id` are assigned to the class list and the id of the element.
+    /// Returns false if origin is not an element
+    pub fn set_attribute(&mut self, origin: Id, name: &str, value: &str) -> bool {
+        self.set_attribute_ns(origin, None, name, Some(value))
+    }
+
+    pub fn remove_attribute(&mut self, origin: Id, name: &str) -> bool {
+        self.set_attribute_ns(origin, None, name, None)
+    }
+
+    /// Sets the attribute, or removes it if `value` is `None`
+    pub fn set_attribute_ns(
+        &mut self,
+        origin: Id,
+        namespace: Option<&str>,
+        name: &str,
+        value: Option<&str>,
+    ) -> bool {
+        let name = QualName::new(namespace, name);
+        self.flush_loose();
+        let el = match self.elements.get_mut(origin) {
+            Some(DomComponent::Element(el)) => el,
+            _ => return false,
+        };
+        self.index.forget(origin, el);
+        let old_value = if name == QualName::new(None, "class") {
+            let old = el.class_list.iter().cloned().collect::<Vec<_>>().join(" ");
+            el.class_list = split_by_whitespace_trimmed(value.unwrap_or(""))
+                .drain(..)
+                .collect();
+            Some(old).filter(|e| !e.is_empty())
+        } else if name == QualName::new(None, "id") {
+            let id_value = first_until_whitespace(value.unwrap_or(""));
+            std::mem::replace(
+                &mut el.id,
+                if id_value.is_empty() { None } else { Some(id_value) },
+            )
+        } else {
+            match value {
+                Some(value) => el.attrs.insert(name.clone(), value.to_string()),
+                None => el.attrs.remove(&name),
+            }
+        };
+        self.index.add(origin, el);
+        self.mutations.push(MutationRecord::Attribute {
+            target: origin,
+            name,
+            old_value,
+        });
+        true
+    }
+
+    /// Returns false if origin is not an element
+    pub fn add_class(&mut self, origin: Id, class_name: &str) -> bool {
+        self.toggle_class(origin, class_name, true)
+    }
+
+    pub fn remove_class(&mut self, origin: Id, class_name: &str) -> bool {
+        self.toggle_class(origin, class_name, false)
+    }
+
+    /// Nothing is recorded if the class list stays the same
+    pub fn toggle_class(&mut self, origin: Id, class_name: &str, enabled: bool) -> bool {
+        self.flush_loose();
+        let el = match self.elements.get_mut(origin) {
+            Some(DomComponent::Element(el)) => el,
+            _ => return false,
+        };
+        if el.class_list.contains(class_name) == enabled {
+            return true;
+        }
+        let old = el.class_list.iter().cloned().collect::<Vec<_>>().join(" ");
+        self.index.forget(origin, el);
+        if enabled {
+            el.class_list.insert(class_name.to_string());
+        } else {
+            el.class_list.remove(class_name);
+        }
+        self.index.add(origin, el);
+        self.mutations.push(MutationRecord::Attribute {
+            target: origin,
+            name: QualName::new(None, "class"),
+            old_value: Some(old).filter(|e| !e.is_empty()),
+        });
+        true
+    }
+
+    /// Returns false if origin is not a text
+    pub fn set_text(&mut self, origin: Id, text: &str) -> bool {
+        let t = match self.elements.get_mut(origin) {
+            Some(DomComponent::Text(t)) => t,
+            _ => return false,
+        };
+        let old_value = std::mem::replace(&mut t.text, text.to_string());
+        self.mutations.push(MutationRecord::Text {
+            target: origin,
+            old_value,
+        });
+        true
+    }
+
+    /// The document node, it holds all the top-level nodes
     pub fn root(&self) -> Id {
         return self.root;
     }
 
+    /// The first top-level element, if there's any
+    pub fn document_element(&self) -> Option<Id> {
+        self.firstlevel_components(self.root)?
+            .iter()
+            .copied()
+            .find(|id| self.get_element(*id).is_some())
+    }
+
     /// Warning: Returns text included
     pub fn firstlevel_components(&self, origin: Id) -> Option<&Vec<Id>> {
         let root = self.elements.get(origin)?;
 
         match root {
-            DomComponent::Element(e) => Some({ &e.children }),
+            DomComponent::Element(e) => Some(&e.children),
+            DomComponent::Document(d) => Some(&d.children),
             _ => None,
         }
     }
@@ -159,35 +794,37 @@ impl DomSystem {
     /// Returns None if origin element is a text or doesn't exist
     /// NOTE: elements after "original" origin are filtered
     pub fn compose_children(&self, origin: Id) -> Option<Vec<Id>> {
-        let root = self.elements.get(origin)?;
-        match root {
-            DomComponent::Element(e) => Some({
-                // A union of elements and corresponding children elements
-                let mut children: Vec<_> = e
-                    .children
-                    .iter()
-                    .filter(|child| {
-                        matches!(
-                            self.elements.get(**child).unwrap(),
-                            DomComponent::Element(_)
-                        )
-                    })
-                    .copied()
-                    .collect();
-                children.append(
-                    &mut e
-                        .children
-                        .iter()
-                        .map(|id| self.compose_children(*id))
-                        .filter(|e| e.is_some())
-                        .flatten()
-                        .flatten()
-                        .collect::<Vec<_>>(),
-                );
-                children
-            }),
-            _ => None,
-        }
+        let children = self.firstlevel_components(origin)?;
+        // A union of elements and corresponding children elements
+        let mut result: Vec<_> = children
+            .iter()
+            .filter(|child| {
+                matches!(
+                    self.elements.get(**child).unwrap(),
+                    DomComponent::Element(_)
+                )
+            })
+            .copied()
+            .collect();
+        result.append(
+            &mut children
+                .iter()
+                .map(|id| self.compose_children(*id))
+                .filter(|e| e.is_some())
+                .flatten()
+                .flatten()
+                .collect::<Vec<_>>(),
+        );
+        Some(result)
+    }
+
+    /// Where the element or text starts in the source document
+    pub fn position(&self, origin: Id) -> Option<SourcePos> {
+        Some(match self.elements.get(origin)? {
+            DomComponent::Element(e) => e.pos,
+            DomComponent::Text(t) => t.pos,
+            DomComponent::Document(_) => SourcePos { line: 0, col: 0 },
+        })
     }
 
     /// WARNING: Includes text
@@ -196,7 +833,13 @@ impl DomSystem {
     }
 
     /// WARNING: Includes text
+    /// NOTE: This is recorded as an unknown mutation, prefer the specific setters
     pub fn get_component_mut(&mut self, origin: Id) -> Option<&mut DomComponent> {
+        if !self.elements.has(origin) {
+            return None;
+        }
+        self.loosen(origin);
+        self.mutations.push(MutationRecord::Unknown { target: origin });
         self.elements.get_mut(origin)
     }
 
@@ -214,7 +857,12 @@ impl DomSystem {
             .flatten();
     }
 
+    /// NOTE: This is recorded as an unknown mutation, prefer the specific setters
     pub fn get_element_mut(&mut self, origin: Id) -> Option<&mut Element> {
+        if self.get_element(origin).is_some() {
+            self.loosen(origin);
+            self.mutations.push(MutationRecord::Unknown { target: origin });
+        }
         return self
             .elements
             .get_mut(origin)
@@ -230,55 +878,50 @@ impl DomSystem {
 
     /// Returns `None` if there's no such origin element, or the origin element doesn't exist  
     pub fn get_elements_by_class_name(&self, origin: Id, class_name: &str) -> Option<Vec<Id>> {
-        let children = self.compose_children(origin);
-        Some(
-            children?
-                .iter()
-                .chain([origin].iter())
-                .copied()
-                .filter(|id| match self.elements.get(*id).unwrap() {
-                    DomComponent::Element(element) => {
-                        return element
-                            .class_list
-                            .iter()
-                            .find(|e| e.as_str() == class_name)
-                            .is_some();
-                    }
-                    _ => unreachable!(),
-                })
-                .collect(),
-        )
+        self.indexed_within(origin, IndexKey::Class(class_name), |element| {
+            element.class_list.contains(class_name)
+        })
     }
 
     pub fn get_elements_by_tag_name(&self, origin: Id, tagname: &str) -> Option<Vec<Id>> {
-        Some(
-            self.compose_children(origin)?
-                .iter()
-                .chain([origin].iter())
-                .copied()
-                .filter(|id| match self.elements.get(*id).unwrap() {
-                    DomComponent::Element(element) => return element.tag.as_str() == tagname,
-                    _ => unreachable!(),
-                })
-                .collect(),
-        )
+        self.indexed_within(origin, IndexKey::Tag(tagname), |element| {
+            element.tag.as_str() == tagname
+        })
+    }
+
+    /// `None` namespace matches elements that are not in any namespace.
+    /// Namespaces aren't indexed, so this looks at every element of the document
+    pub fn get_elements_by_namespace(
+        &self,
+        origin: Id,
+        namespace: Option<&str>,
+    ) -> Option<Vec<Id>> {
+        self.indexed_within(origin, IndexKey::All, |element| {
+            element.namespace.as_deref() == namespace
+        })
+    }
+
+    /// Indices of the node and its ancestors in the children of their parents, from the root down.
+    /// Comparing them orders nodes as they come in the document
+    fn tree_path(&self, origin: Id) -> Vec<usize> {
+        let mut path = vec![];
+        let mut current = origin;
+        while let Some(parent) = self.parent(current) {
+            let siblings = self.firstlevel_components(parent).unwrap();
+            path.push(siblings.iter().position(|e| *e == current).unwrap());
+            current = parent;
+        }
+        path.reverse();
+        path
     }
 
+    /// Ids aren't required to be unique, this returns the first element with the id in document order
     pub fn get_element_by_id(&self, origin: Id, identifier: &str) -> Option<Id> {
-        self.compose_children(origin)?
-            .iter()
-            .chain([origin].iter())
-            .copied()
-            .find(|id| match self.elements.get(*id).unwrap() {
-                DomComponent::Element(element) => {
-                    return element
-                        .id
-                        .as_ref()
-                        .map(|e| e.as_str() == identifier)
-                        .unwrap_or(false);
-                }
-                _ => unreachable!(),
-            })
+        self.indexed_within(origin, IndexKey::Id(identifier), |element| {
+            element.id.as_deref() == Some(identifier)
+        })?
+        .first()
+        .copied()
     }
 }
 
@@ -286,11 +929,11 @@ impl DomSystem {
 fn test_dom_parser() {
     let input = r#"<?xml version="1.0" encoding="utf-8"?><div param="a">test<span></span>another text</div>"#;
     let sys = DomSystem::from_xml(input).unwrap();
-    let el = sys.elements.get(sys.root).unwrap();
+    let el = sys.elements.get(sys.document_element().unwrap()).unwrap();
     match el {
         DomComponent::Element(el) => {
             assert_eq!(el.tag, "div");
-            assert_eq!(el.attrs.get("param").unwrap(), "a");
+            assert_eq!(el.attr("param").unwrap(), "a");
             dbg!(&el.children);
             match sys.elements.get(el.children[0]).unwrap() {
                 DomComponent::Text(t) => assert_eq!(t.text, "test"),
@@ -344,3 +987,160 @@ fn test_dom_parser_selector() {
     let el = sys.get_elements_by_class_name(outter, "inner").unwrap();
     assert_eq!(el.len(), 4);
 }
+
+#[test]
+fn test_dom_parser_namespaces() {
+    let input = r#"<?xml version="1.0" encoding="utf-8"?>
+<Div xmlns:w="urn:widgets" class="outer">
+    <w:Slider w:min="0" max="10"/>
+    <Div xmlns="urn:widgets"><Knob/></Div>
+</Div>
+    "#;
+
+    let sys = DomSystem::from_xml(input).unwrap();
+    let root = sys.get_element(sys.document_element().unwrap()).unwrap();
+    assert_eq!(root.tag, "Div");
+    assert_eq!(root.namespace, None);
+    assert_eq!(root.lookup_namespace_uri("w"), Some("urn:widgets"));
+
+    let slider = sys.get_element(root.children[0]).unwrap();
+    assert_eq!(slider.tag, "Slider");
+    assert_eq!(slider.namespace.as_deref(), Some("urn:widgets"));
+    assert_eq!(slider.attr_ns(Some("urn:widgets"), "min"), Some("0"));
+    assert_eq!(slider.attr("min"), None);
+    assert_eq!(slider.attr("max"), Some("10"));
+
+    let el = sys
+        .get_elements_by_namespace(sys.root(), Some("urn:widgets"))
+        .unwrap();
+    assert_eq!(el.len(), 3);
+    let el = sys.get_elements_by_namespace(sys.root(), None).unwrap();
+    assert_eq!(el.len(), 1);
+}
+
+#[test]
+fn test_dom_positions() {
+    let input = "<Div>\n  <Span>text</Span>\n</Div>";
+    let sys = DomSystem::from_xml(input).unwrap();
+    let root = sys.document_element().unwrap();
+    assert_eq!(sys.position(root), Some(SourcePos { line: 0, col: 0 }));
+    let span = sys.get_element(root).unwrap().children[0];
+    assert_eq!(sys.position(span), Some(SourcePos { line: 1, col: 2 }));
+    let text = sys.get_element(span).unwrap().children[0];
+    assert_eq!(sys.position(text), Some(SourcePos { line: 1, col: 8 }));
+
+    let input = "<Div>\n  <Span></Div>\n</Div>";
+    let error = DomSystem::from_xml(input).unwrap_err();
+    assert_eq!(error.pos.line, 1);
+    assert_eq!(error.context, "  <Span></Div>");
+    let message = error.to_string();
+    let mut lines = message.lines();
+    assert!(lines.next().unwrap().starts_with("XML error: "));
+    assert_eq!(lines.next(), Some("  <Span></Div>"));
+    assert_eq!(lines.next().unwrap().len(), error.pos.col + 1);
+}
+
+#[test]
+fn test_dom_document_and_fragments() {
+    let sys = DomSystem::from_xml("").unwrap();
+    assert_eq!(sys.firstlevel_components(sys.root()).unwrap().len(), 0);
+    assert_eq!(sys.document_element(), None);
+
+    let input = r#"<?xml version="1.0" encoding="utf-8"?>leading<Div/><Span/>"#;
+    let sys = DomSystem::from_xml(input).unwrap();
+    let top = sys.firstlevel_components(sys.root()).unwrap();
+    assert_eq!(top.len(), 3);
+    assert!(matches!(sys.get_component(top[0]), Some(DomComponent::Text(t)) if t.text == "leading"));
+    assert_eq!(sys.get_element(sys.document_element().unwrap()).unwrap().tag, "Div");
+    assert_eq!(sys.position(top[1]), Some(SourcePos { line: 0, col: 45 }));
+
+    let mut sys = DomSystem::from_xml(r#"<Div xmlns:w="urn:widgets"><Span/></Div>"#).unwrap();
+    let root = sys.document_element().unwrap();
+    let ids = sys
+        .parse_fragment(r#"text <w:Knob class="big"/><Div id="x"/>"#, root)
+        .unwrap();
+    assert_eq!(ids.len(), 3);
+    assert_eq!(sys.get_element(root).unwrap().children.len(), 4);
+    let knob = sys.get_element(ids[1]).unwrap();
+    assert_eq!(knob.namespace.as_deref(), Some("urn:widgets"));
+    assert_eq!(sys.get_elements_by_class_name(root, "big").unwrap(), vec![ids[1]]);
+    assert_eq!(sys.get_element_by_id(sys.root(), "x"), Some(ids[2]));
+    assert_eq!(sys.position(ids[1]), Some(SourcePos { line: 0, col: 5 }));
+
+    let error = sys.parse_fragment("<Div>", root).unwrap_err();
+    assert_eq!(error.context, "<Div>");
+    assert!(sys.parse_fragment("<Div/>", ids[0]).is_err());
+
+    // A later element can take the slot of a removed one, the first one in the document still wins
+    sys.remove(ids[1]);
+    let later = sys.parse_fragment(r#"<Div id="x"/>"#, root).unwrap();
+    assert!(later[0] < ids[2]);
+    assert_eq!(sys.get_element_by_id(sys.root(), "x"), Some(ids[2]));
+    let divs = sys.get_elements_by_tag_name(root, "Div").unwrap();
+    assert_eq!(divs, vec![root, ids[2], later[0]]);
+    assert_eq!(sys.get_elements_by_namespace(root, None).unwrap()[2..], [ids[2], later[0]]);
+}
+
+#[test]
+fn test_dom_prolog() {
+    let sys = DomSystem::from_xml(r#"<?xml version="1.0"?><!DOCTYPE Div><Div/>"#).unwrap();
+    let root = sys.document_element().unwrap();
+    assert_eq!(sys.get_element(root).unwrap().tag, "Div");
+    assert_eq!(sys.position(root), Some(SourcePos { line: 0, col: 35 }));
+
+    let input = r#"<?xml version="1.0"?>
+<!-- A comment -->
+<!DOCTYPE Div [
+    <!ELEMENT Div ANY>
+]>
+<?style sheet?>
+<Div><Span/></Div>"#;
+    let sys = DomSystem::from_xml(input).unwrap();
+    let root = sys.document_element().unwrap();
+    assert_eq!(sys.get_element(root).unwrap().children.len(), 1);
+    assert_eq!(sys.position(root), Some(SourcePos { line: 6, col: 0 }));
+
+    // Without a declaration the doctype still goes in front of the wrapper
+    let sys = DomSystem::from_xml("<!DOCTYPE Span><Span/>").unwrap();
+    assert_eq!(sys.get_element(sys.document_element().unwrap()).unwrap().tag, "Span");
+}
+
+#[test]
+fn test_dom_mutations() {
+    let input = r#"<Div id="a" class="x"><Span>text</Span><Div/></Div>"#;
+    let mut sys = DomSystem::from_xml(input).unwrap();
+    let root = sys.document_element().unwrap();
+    let span = sys.get_element(root).unwrap().children[0];
+    let div = sys.get_element(root).unwrap().children[1];
+    let text = sys.get_element(span).unwrap().children[0];
+    assert_eq!(sys.parent(text), Some(span));
+    assert_eq!(sys.take_mutations(), vec![]);
+
+    assert!(sys.set_attribute(root, "class", "y z"));
+    assert!(sys.set_attribute(root, "id", "b"));
+    assert!(sys.set_attribute(span, "title", "hi"));
+    assert!(sys.add_class(span, "kw"));
+    assert!(sys.add_class(span, "kw"));
+    assert!(sys.set_text(text, "other"));
+    assert!(!sys.set_text(span, "other"));
+    assert!(sys.remove(span));
+    sys.get_element_mut(div).unwrap();
+
+    assert_eq!(
+        sys.take_mutations(),
+        vec![
+            MutationRecord::Attribute { target: root, name: QualName::new(None, "class"), old_value: Some("x".to_string()) },
+            MutationRecord::Attribute { target: root, name: QualName::new(None, "id"), old_value: Some("a".to_string()) },
+            MutationRecord::Attribute { target: span, name: QualName::new(None, "title"), old_value: None },
+            MutationRecord::Attribute { target: span, name: QualName::new(None, "class"), old_value: None },
+            MutationRecord::Text { target: text, old_value: "text".to_string() },
+            MutationRecord::ChildList { target: root, added: vec![], removed: vec![span] },
+            MutationRecord::Unknown { target: div },
+        ]
+    );
+    assert_eq!(sys.take_mutations(), vec![]);
+    assert_eq!(sys.get_element(root).unwrap().children, vec![div]);
+    assert!(sys.get_component(text).is_none());
+    assert_eq!(sys.get_element(root).unwrap().id(), Some("b"));
+    assert_eq!(sys.get_elements_by_class_name(root, "z").unwrap(), vec![root]);
+}
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use crate::atoms::*;
+
+/// Where a glyph is in the atlas and how it's placed on a line
+#[derive(Debug, Clone, Copy)]
+pub struct Glyph {
+    /// Index of the atlas page the glyph is on
+    pub page: usize,
+    pub source: Recti,
+    /// From the pen position to the top left corner of the glyph
+    pub offset: Vec2i,
+    /// How far the pen moves after the glyph
+    pub advance: i32,
+}
+
+/// Image the glyphs are taken from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atlas {
+    File(String),
+    /// Coverage of every pixel, row by row
+    Pixels { width: u32, height: u32, data: Vec<u8> }
+}
+
+/// Glyphs of a font atlas, used both to measure text in layout and to draw it
+#[derive(Debug)]
+pub struct Font {
+    pub pages: Vec<Atlas>,
+    glyphs: HashMap<char, Glyph>,
+    /// Adjustments of the advance between pairs of characters
+    kerning: HashMap<(char, char), i32>,
+    /// Drawn for characters without a glyph of their own, wide ones take twice its advance
+    missing: Glyph,
+    pub line_height: i32,
+}
+
+impl Font {
+    /// Atlas of equally sized cells filled row by row. `ranges` list the characters in the order their glyphs appear,
+    /// starting from the second cell, the first one is the glyph for missing characters.
+    /// Wide characters take two cells next to each other
+    pub fn grid(cell: Vec2i, columns: i32, ranges: &[(char, char)]) -> Self {
+        let rect = |index: i32, cells: i32| Rect::new(index%columns*cell.x, index/columns*cell.y, cell.x*cells, cell.y);
+        let mut glyphs = HashMap::new();
+        let mut index = 1;
+        for c in ranges.iter().flat_map(|(first, last)| *first..=*last) {
+            let width = char_width(c) as i32;
+            let cells = width.max(1);
+            if index%columns+cells > columns {
+                index += columns-index%columns;
+            }
+            // Combining marks are drawn over the character before them
+            let offset = Vec2::new(if width == 0 { -cell.x } else { 0 }, 0);
+            glyphs.insert(c, Glyph { page: 0, source: rect(index, cells), offset, advance: cell.x*width });
+            index += cells;
+        }
+        let missing = Glyph { page: 0, source: rect(0, 1), offset: Vec2::new(0, 0), advance: cell.x };
+        Self { pages: vec![], glyphs, kerning: HashMap::new(), missing, line_height: cell.y }
+    }
+
+    /// The one in `font.bmp`, printable ASCII in 8x16 cells, 64 to a row
+    pub fn builtin() -> Self {
+        let mut font = Self::grid(Vec2::new(8, 16), 64, &[(' ', '~')]);
+        font.pages.push(Atlas::File("font.bmp".to_string()));
+        font
+    }
+
+    /// Loads a BMFont descriptor in the text format (`.fnt`) or a BDF font (`.bdf`).
+    /// Pages of a BMFont are looked up next to the descriptor
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let path = std::path::Path::new(path);
+        let font = match path.extension().and_then(|e| e.to_str()) {
+            Some("fnt") => Self::from_bmfont(&source, path.parent().unwrap_or(std::path::Path::new(""))),
+            Some("bdf") => Self::from_bdf(&source),
+            _ => Err("Unknown font format, expected .fnt or .bdf".to_string())
+        };
+        font.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn with_glyphs(pages: Vec<Atlas>, glyphs: HashMap<char, Glyph>, kerning: HashMap<(char, char), i32>, missing: Option<Glyph>, line_height: i32) -> Result<Self, String> {
+        // The replacement character if there is no glyph meant for this
+        let missing = missing.or_else(|| ['\u{FFFD}', '?', ' '].iter().find_map(|c| glyphs.get(c).copied()))
+            .ok_or_else(|| "No glyph to draw missing characters with".to_string())?;
+        Ok(Self { pages, glyphs, kerning, missing, line_height })
+    }
+
+    /// Text format of AngelCode BMFont, lines like `char id=65 x=0 y=0 width=8 height=12 xoffset=0 yoffset=2 xadvance=9 page=0`
+    pub fn from_bmfont(source: &str, directory: &std::path::Path) -> Result<Self, String> {
+        let mut pages = vec![];
+        let mut glyphs = HashMap::new();
+        let mut kerning = HashMap::new();
+        let mut line_height = None;
+        let mut missing = None;
+        for (n, line) in source.lines().enumerate() {
+            let (tag, pairs) = bmfont_line(line);
+            let int = |key: &str| -> Result<i32, String> {
+                pairs.get(key).and_then(|e| e.parse().ok()).ok_or_else(|| format!("Line {}: expected a number in '{}'", n+1, key))
+            };
+            let character = |key: &str| -> Result<Option<char>, String> {
+                // -1 is the glyph for missing characters
+                Ok(match int(key)? {
+                    -1 => None,
+                    code => Some(std::char::from_u32(code as u32).ok_or_else(|| format!("Line {}: invalid character {}", n+1, code))?)
+                })
+            };
+            match tag {
+                "common" => line_height = Some(int("lineHeight")?),
+                "page" => {
+                    let file = pairs.get("file").ok_or_else(|| format!("Line {}: page without a file", n+1))?;
+                    let id = int("id")? as usize;
+                    pages.resize(pages.len().max(id+1), Atlas::File(String::new()));
+                    pages[id] = Atlas::File(directory.join(file).to_string_lossy().into_owned());
+                },
+                "char" => {
+                    let glyph = Glyph {
+                        page: int("page").unwrap_or(0) as usize,
+                        source: Rect::new(int("x")?, int("y")?, int("width")?, int("height")?),
+                        offset: Vec2::new(int("xoffset")?, int("yoffset")?),
+                        advance: int("xadvance")?,
+                    };
+                    match character("id")? {
+                        Some(c) => { glyphs.insert(c, glyph); },
+                        None => missing = Some(glyph)
+                    }
+                },
+                "kerning" => {
+                    if let (Some(first), Some(second)) = (character("first")?, character("second")?) {
+                        kerning.insert((first, second), int("amount")?);
+                    }
+                },
+                _ => {}
+            }
+        }
+        let line_height = line_height.ok_or_else(|| "Missing 'common' line".to_string())?;
+        Self::with_glyphs(pages, glyphs, kerning, missing, line_height)
+    }
+
+    /// Glyph Bitmap Distribution Format, glyphs are packed into an atlas of their own
+    pub fn from_bdf(source: &str) -> Result<Self, String> {
+        const ATLAS_WIDTH: i32 = 512;
+        let mut bitmaps: Vec<(char, Glyph, Vec<Vec<bool>>)> = vec![];
+        let mut bounding_box = Vec2::new(0, 0);
+        let (mut ascent, mut descent, mut default_char) = (None, None, None);
+        let mut current: Option<(Option<char>, i32, Recti)> = None;
+        let mut bitmap: Option<Vec<Vec<bool>>> = None;
+        for (n, line) in source.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let numbers: Vec<i32> = words.filter_map(|e| e.parse().ok()).collect();
+            let number = |i: usize| numbers.get(i).copied().ok_or_else(|| format!("Line {}: expected {} numbers after {}", n+1, i+1, keyword));
+            if let Some(rows) = &mut bitmap {
+                if keyword == "ENDCHAR" {
+                    let (c, advance, bbx) = current.take().ok_or_else(|| format!("Line {}: ENDCHAR without STARTCHAR", n+1))?;
+                    let rows = bitmap.take().unwrap();
+                    if let Some(c) = c {
+                        let glyph = Glyph { page: 0, source: Rect::new(0, 0, bbx.size.x, bbx.size.y), offset: bbx.pos, advance };
+                        bitmaps.push((c, glyph, rows));
+                    }
+                } else {
+                    // Every byte is two hex digits
+                    if !keyword.is_ascii() || keyword.len()%2 != 0 {
+                        return Err(format!("Line {}: invalid bitmap row", n+1));
+                    }
+                    let bytes = (0..keyword.len()/2).map(|i| u8::from_str_radix(&keyword[i*2..i*2+2], 16))
+                        .collect::<Result<Vec<u8>, _>>().map_err(|_| format!("Line {}: invalid bitmap row", n+1))?;
+                    rows.push((0..bytes.len()*8).map(|i| bytes[i/8] & (0x80 >> (i%8)) != 0).collect());
+                }
+                continue;
+            }
+            match keyword {
+                "FONTBOUNDINGBOX" => bounding_box = Vec2::new(number(0)?, number(1)?),
+                "FONT_ASCENT" => ascent = Some(number(0)?),
+                "FONT_DESCENT" => descent = Some(number(0)?),
+                "DEFAULT_CHAR" => default_char = std::char::from_u32(number(0)? as u32),
+                "STARTCHAR" => current = Some((None, 0, Rect::new(0, 0, 0, 0))),
+                "ENCODING" | "DWIDTH" | "BBX" => {
+                    let glyph = current.as_mut().ok_or_else(|| format!("Line {}: {} outside of a character", n+1, keyword))?;
+                    match keyword {
+                        // -1 marks glyphs without a standard encoding
+                        "ENCODING" => glyph.0 = std::char::from_u32(number(0)? as u32).filter(|_| numbers[0] >= 0),
+                        "DWIDTH" => glyph.1 = number(0)?,
+                        _ => glyph.2 = Rect::new(number(2)?, number(3)?, number(0)?, number(1)?)
+                    }
+                },
+                "BITMAP" => bitmap = Some(vec![]),
+                _ => {}
+            }
+        }
+        let ascent = ascent.unwrap_or(bounding_box.y);
+        let line_height = ascent+descent.unwrap_or(0);
+
+        // Cells fit the bounding box and any glyph whose BBX is larger than it, so glyphs don't overlap
+        let cell = bitmaps.iter().fold(bounding_box, |cell, (_, glyph, _)| {
+            Vec2::new(cell.x.max(glyph.source.size.x), cell.y.max(glyph.source.size.y))
+        });
+        // Shelves as high as a cell, the offset is turned from the baseline to the top of the line
+        let columns = (ATLAS_WIDTH/cell.x.max(1)).max(1);
+        let rows = (bitmaps.len() as i32+columns-1)/columns;
+        let (width, height) = (ATLAS_WIDTH as u32, (rows*cell.y.max(1)) as u32);
+        let mut data = vec![0; (width*height) as usize];
+        let mut glyphs = HashMap::new();
+        for (i, (c, mut glyph, rows)) in bitmaps.into_iter().enumerate() {
+            let corner = Vec2::new(i as i32%columns*cell.x, i as i32/columns*cell.y);
+            for (y, row) in rows.iter().enumerate().take(glyph.source.size.y as usize) {
+                for (x, set) in row.iter().enumerate().take(glyph.source.size.x as usize) {
+                    let (px, py) = (corner.x+x as i32, corner.y+y as i32);
+                    if *set && px < width as i32 && py < height as i32 {
+                        data[(py as u32*width+px as u32) as usize] = 255;
+                    }
+                }
+            }
+            glyph.source.pos = corner;
+            glyph.offset = Vec2::new(glyph.offset.x, ascent-glyph.source.size.y-glyph.offset.y);
+            glyphs.insert(c, glyph);
+        }
+        let missing = default_char.and_then(|e| glyphs.get(&e).copied());
+        Self::with_glyphs(vec![Atlas::Pixels { width, height, data }], glyphs, HashMap::new(), missing, line_height)
+    }
+
+    /// `None` for characters that aren't drawn, like tabs, newlines and zero width ones without a glyph
+    pub fn glyph(&self, c: char) -> Option<Glyph> {
+        if let Some(glyph) = self.glyphs.get(&c) {
+            return Some(*glyph)
+        }
+        match char_width(c) {
+            0 => None,
+            width => Some(Glyph { advance: self.missing.advance*width as i32, ..self.missing })
+        }
+    }
+
+    pub fn kerning(&self, first: char, second: char) -> i32 {
+        self.kerning.get(&(first, second)).copied().unwrap_or(0)
+    }
+
+    pub fn advance(&self, c: char) -> i32 {
+        match c {
+            '\t' => self.advance(' ')*4,
+            c => self.glyph(c).map_or(0, |e| e.advance)
+        }
+    }
+
+    pub fn measure(&self, text: &str) -> i32 {
+        let pairs = text.chars().zip(text.chars().skip(1));
+        text.chars().map(|c| self.advance(c)).sum::<i32>()+pairs.map(|(a, b)| self.kerning(a, b)).sum::<i32>()
+    }
+}
+
+/// Tag of a BMFont line and its `key=value` pairs, values may be quoted
+fn bmfont_line(line: &str) -> (&str, HashMap<&str, String>) {
+    let line = line.trim();
+    let (tag, mut rest) = line.split_at(line.find(' ').unwrap_or(line.len()));
+    let mut pairs = HashMap::new();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim();
+        rest = &rest[eq+1..];
+        let value = if rest.starts_with('"') {
+            let end = rest[1..].find('"').map_or(rest.len(), |e| e+1);
+            let value = rest[1..end].to_string();
+            rest = &rest[(end+1).min(rest.len())..];
+            value
+        } else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            let value = rest[..end].to_string();
+            rest = &rest[end..];
+            value
+        };
+        pairs.insert(key, value);
+    }
+    (tag, pairs)
+}
+
+/// Fonts by family name, anything without a font of its own uses the builtin one
+#[derive(Debug)]
+pub struct Fonts {
+    fonts: Vec<Font>,
+    families: HashMap<String, usize>,
+}
+
+impl Fonts {
+    pub fn new() -> Self {
+        Self { fonts: vec![Font::builtin()], families: HashMap::new() }
+    }
+
+    pub fn add(&mut self, family: &str, font: Font) {
+        self.families.insert(family.to_string(), self.fonts.len());
+        self.fonts.push(font);
+    }
+
+    /// Index of the first of the families there is a font for
+    pub fn select(&self, families: &[String]) -> usize {
+        families.iter().find_map(|e| self.families.get(e).copied()).unwrap_or(0)
+    }
+
+    pub fn get(&self, index: usize) -> &Font {
+        &self.fonts[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Font> {
+        self.fonts.iter()
+    }
+}
+
+/// A font declared with `@font-face`
+#[derive(Debug, PartialEq)]
+pub struct FontFace {
+    pub family: String,
+    /// Path of the descriptor
+    pub src: String,
+}
+
+/// Columns a character takes in a monospace font, 2 for East Asian wide and fullwidth characters
+/// and 0 for control characters and the ones combining with the previous one
+pub fn char_width(c: char) -> u32 {
+    const ZERO: [(u32, u32); 14] = [
+        (0x0000, 0x001F), (0x007F, 0x009F), (0x0300, 0x036F), (0x0483, 0x0489), (0x0591, 0x05BD), (0x0610, 0x061A),
+        (0x064B, 0x065F), (0x1AB0, 0x1AFF), (0x1DC0, 0x1DFF), (0x200B, 0x200F), (0x2060, 0x2064), (0x20D0, 0x20FF),
+        (0xFE00, 0xFE0F), (0xFE20, 0xFE2F),
+    ];
+    const WIDE: [(u32, u32); 16] = [
+        (0x1100, 0x115F), (0x2E80, 0x303E), (0x3041, 0x33FF), (0x3400, 0x4DBF), (0x4E00, 0x9FFF), (0xA000, 0xA4CF),
+        (0xA960, 0xA97F), (0xAC00, 0xD7A3), (0xF900, 0xFAFF), (0xFE30, 0xFE4F), (0xFF00, 0xFF60), (0xFFE0, 0xFFE6),
+        (0x1F300, 0x1F64F), (0x1F900, 0x1F9FF), (0x20000, 0x2FFFD), (0x30000, 0x3FFFD),
+    ];
+    let within = |ranges: &[(u32, u32)]| ranges.iter().any(|(first, last)| (*first..=*last).contains(&(c as u32)));
+    if c == '\u{FEFF}' || within(&ZERO) {
+        0
+    } else if within(&WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+#[test]
+fn test_font_metrics() {
+    let font = Font::builtin();
+    assert_eq!(font.measure("Hello"), 40);
+    assert_eq!(font.measure("a\tb"), 48);
+    // Missing glyphs still take their columns, multi-byte characters count once
+    assert_eq!(font.measure("файл"), 32);
+    assert_eq!(font.measure("日本語"), 48);
+    assert_eq!(font.measure("e\u{301}"), 8);
+    let missing = font.glyph('日').unwrap();
+    assert_eq!((missing.source.pos.x, missing.source.pos.y), (0, 0));
+    let tilde = font.glyph('~').unwrap();
+    assert_eq!((tilde.source.pos.x, tilde.source.pos.y), (95*8%512, 16));
+
+    // More glyphs than fit a row, wide ones don't get split between rows
+    let font = Font::grid(Vec2::new(8, 16), 4, &[('a', 'b'), ('日', '日'), ('ä', 'ä')]);
+    let source = |c| { let r = font.glyph(c).unwrap().source; (r.pos.x, r.pos.y, r.size.x) };
+    assert_eq!(source('b'), (16, 0, 8));
+    assert_eq!(source('日'), (0, 16, 16));
+    assert_eq!(source('ä'), (16, 16, 8));
+    assert_eq!(font.advance('日'), 16);
+}
+
+#[test]
+fn test_font_loading() {
+    let fnt = "info face=\"Test Sans\" size=12\n\
+               common lineHeight=14 base=11 scaleW=64 scaleH=64 pages=1\n\
+               page id=0 file=\"test_0.bmp\"\n\
+               chars count=3\n\
+               char id=65   x=0  y=0 width=7 height=9 xoffset=0 yoffset=2 xadvance=8 page=0\n\
+               char id=86   x=8  y=0 width=7 height=9 xoffset=0 yoffset=2 xadvance=7 page=0\n\
+               char id=63   x=16 y=0 width=5 height=9 xoffset=1 yoffset=2 xadvance=6 page=0\n\
+               kernings count=1\n\
+               kerning first=65 second=86 amount=-2";
+    let font = Font::from_bmfont(fnt, std::path::Path::new("fonts")).unwrap();
+    assert_eq!(font.line_height, 14);
+    assert_eq!(font.pages, vec![Atlas::File(std::path::Path::new("fonts").join("test_0.bmp").to_string_lossy().into_owned())]);
+    assert_eq!(font.measure("AV"), 13);
+    assert_eq!(font.measure("VA"), 15);
+    let v = font.glyph('V').unwrap();
+    assert_eq!((v.source.pos.x, v.offset.y), (8, 2));
+    // Without a glyph for missing characters the question mark is used
+    assert_eq!(font.glyph('x').unwrap().source.pos.x, 16);
+
+    let bdf = "STARTFONT 2.1\nFONTBOUNDINGBOX 4 6 0 -1\nSTARTPROPERTIES 2\nFONT_ASCENT 5\nFONT_DESCENT 1\nENDPROPERTIES\nCHARS 2\n\
+               STARTCHAR question\nENCODING 63\nDWIDTH 5 0\nBBX 3 3 0 2\nBITMAP\nE0\n20\n40\nENDCHAR\n\
+               STARTCHAR A\nENCODING 65\nDWIDTH 4 0\nBBX 4 6 0 -1\nBITMAP\n60\n90\nF0\n90\n90\n00\nENDCHAR\nENDFONT";
+    let font = Font::from_bdf(bdf).unwrap();
+    assert_eq!(font.line_height, 6);
+    assert_eq!(font.measure("A?"), 9);
+    let question = font.glyph('?').unwrap();
+    // Raised by 2 from the baseline at 5, so it starts at the top of the line
+    assert_eq!((question.source.size.x, question.source.size.y, question.offset.y), (3, 3, 0));
+    let a = font.glyph('A').unwrap();
+    assert_eq!((a.source.pos.x, a.offset.y), (4, 0));
+    match &font.pages[0] {
+        Atlas::Pixels { width, data, .. } => {
+            let row = |y: u32| (0..8).map(|x| data[(y*width+x) as usize] > 0).collect::<Vec<_>>();
+            assert_eq!(row(0), vec![true, true, true, false, false, true, true, false]);
+            assert_eq!(row(2), vec![false, true, false, false, true, true, true, true]);
+        },
+        e => panic!("{:?}", e)
+    }
+
+    // A glyph larger than the bounding box gets a larger cell instead of spilling into the next one
+    let bdf = "STARTFONT 2.1\nFONTBOUNDINGBOX 2 2 0 0\nCHARS 2\n\
+               STARTCHAR wide\nENCODING 87\nDWIDTH 4 0\nBBX 4 3 0 0\nBITMAP\nF0\nF0\nF0\nENDCHAR\n\
+               STARTCHAR question\nENCODING 63\nDWIDTH 2 0\nBBX 1 1 0 0\nBITMAP\n80\nENDCHAR\nENDFONT";
+    let wide = Font::from_bdf(bdf).unwrap();
+    assert_eq!(wide.glyph('?').unwrap().source.pos.x, 4);
+    match &wide.pages[0] {
+        Atlas::Pixels { width, height, data } => {
+            assert_eq!(*height, 3);
+            assert_eq!((0..6).map(|x| data[x] > 0).collect::<Vec<_>>(), vec![true, true, true, true, true, false]);
+            assert_eq!(data[(2*width) as usize+4], 0);
+        },
+        e => panic!("{:?}", e)
+    }
+    for row in ["E", "ÉÉ", "G0"] {
+        let bdf = format!("STARTFONT 2.1\nFONTBOUNDINGBOX 2 2 0 0\nSTARTCHAR a\nENCODING 97\nBBX 2 1 0 0\nBITMAP\n{}\nENDCHAR\nENDFONT", row);
+        assert_eq!(Font::from_bdf(&bdf).unwrap_err(), "Line 7: invalid bitmap row");
+    }
+
+    let mut fonts = Fonts::new();
+    fonts.add("Test", font);
+    assert_eq!(fonts.select(&["Other".to_string(), "Test".to_string()]), 1);
+    assert_eq!(fonts.select(&[]), 0);
+}
@@ -9,6 +9,7 @@ mod xml_ui;
 mod css_parser;
 mod css_gen;
 mod css_matcher;
+mod font;
 extern crate sdl2;
 extern crate ron;
 extern crate xml;
@@ -18,10 +19,9 @@ use crate::atoms::*;
 use serde::{Deserialize, Serialize};
 use sdl2::gfx::primitives::DrawRenderer;
 use sdl2::pixels::Color;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 use std::time::Duration;
-use std::collections::hash_map::*;
 
 #[derive(Debug, Deserialize)]
 struct TexRect(u32, u32, u32, u32);
@@ -54,11 +54,53 @@ pub struct FontData
 {
     pub bold: bool,
     pub italic: bool,
+    /// Glyphs are drawn this many times larger
+    pub scale: i32,
+    /// Added to the advance of every glyph
     pub letter_spacing: i32,
     pub row_spacing: i32,
     pub color: (u8, u8, u8, u8)
 }
 
+/// Distance scrolled by one notch of the mouse wheel
+const SCROLL_STEP: f32 = 16.;
+const SCROLLBAR_WIDTH: f32 = 6.;
+
+fn to_srect(rect: Rectf) -> SRect
+{
+    SRect::new(rect.pos.x as i32, rect.pos.y as i32, rect.size.x as u32, rect.size.y as u32)
+}
+
+/// Scrollbars are drawn over the content along the right and the bottom edge of the frame
+pub fn render_scrollbars(canv: &mut sdl2::render::WindowCanvas, frame: &ui::Frame)
+{
+    let area = match frame.scroll { Some(area) => area, None => return };
+    let rect = frame.rect;
+    canv.set_clip_rect(frame.clip.map(to_srect));
+    for (axis, bar) in area.bars.iter().enumerate()
+    {
+        if !bar
+        {
+            continue;
+        }
+        let (length, content, offset) = if axis == 0 { (rect.size.x, area.content.x, area.offset.x) } else { (rect.size.y, area.content.y, area.offset.y) };
+        let (thumb, start) = (length*length/content, offset*length/content);
+        let (track, handle) = if axis == 0
+        {
+            (Rectf::new(rect.pos.x, rect.pos.y+rect.size.y-SCROLLBAR_WIDTH, length, SCROLLBAR_WIDTH), Rectf::new(rect.pos.x+start, rect.pos.y+rect.size.y-SCROLLBAR_WIDTH, thumb, SCROLLBAR_WIDTH))
+        }
+        else
+        {
+            (Rectf::new(rect.pos.x+rect.size.x-SCROLLBAR_WIDTH, rect.pos.y, SCROLLBAR_WIDTH, length), Rectf::new(rect.pos.x+rect.size.x-SCROLLBAR_WIDTH, rect.pos.y+start, SCROLLBAR_WIDTH, thumb))
+        };
+        canv.set_draw_color(Color::RGBA(0, 0, 0, 64));
+        canv.fill_rect(to_srect(track)).unwrap();
+        canv.set_draw_color(Color::RGBA(128, 128, 128, 200));
+        canv.fill_rect(to_srect(handle)).unwrap();
+    }
+    canv.set_clip_rect(None);
+}
+
 pub fn render_copy_cut(canv: &mut sdl2::render::WindowCanvas, tex: &sdl2::render::Texture, src: SRect, dest: SRect, cutout: Option<SRect>)
 {
     canv.set_clip_rect(cutout);
@@ -67,48 +109,103 @@ pub fn render_copy_cut(canv: &mut sdl2::render::WindowCanvas, tex: &sdl2::render
 }
 
 
-pub fn render_text(canv: &mut sdl2::render::WindowCanvas, tex: &mut sdl2::render::Texture, text: &str, pos: Vec2<i32>, dat: &FontData, ren: &RenderData)
+/// A texture for every page of every font, in the order of `fonts`
+pub fn load_font_textures<'a>(creator: &'a sdl2::render::TextureCreator<sdl2::video::WindowContext>, fonts: &font::Fonts) -> Result<Vec<Vec<sdl2::render::Texture<'a>>>, String>
+{
+    let mut textures = vec![];
+    for font in fonts.iter()
+    {
+        let mut pages = vec![];
+        for page in font.pages.iter()
+        {
+            let mut texture = match page
+            {
+                font::Atlas::File(path) => sdl2::surface::Surface::load_bmp(path).map_err(|e| format!("{}: {}", path, e))?.as_texture(creator),
+                font::Atlas::Pixels { width, height, data } =>
+                {
+                    // White, so the color can be changed with color mod
+                    let mut rgba: Vec<u8> = data.iter().flat_map(|a| vec![255, 255, 255, *a]).collect();
+                    let surface = sdl2::surface::Surface::from_data(&mut rgba, *width, *height, width*4, sdl2::pixels::PixelFormatEnum::RGBA32)?;
+                    surface.as_texture(creator)
+                }
+            }.map_err(|e| e.to_string())?;
+            texture.set_blend_mode(sdl2::render::BlendMode::Blend);
+            pages.push(texture);
+        }
+        textures.push(pages);
+    }
+    Ok(textures)
+}
+
+pub fn render_text(canv: &mut sdl2::render::WindowCanvas, pages: &mut [sdl2::render::Texture], text: &str, pos: Vec2<i32>, font: &font::Font, dat: &FontData, ren: &RenderData)
 {
     let mut x = 0;
     let mut y = 0;
+    for tex in pages.iter_mut()
+    {
+        tex.set_alpha_mod(dat.color.3);
+        tex.set_color_mod(dat.color.0, dat.color.1, dat.color.2);
+    }
+    let mut previous = None;
     for chr in text.chars()
     {
         if chr == '\n'
         {
             y += dat.row_spacing;
             x = 0;
+            previous = None;
             continue;
         }
-        if chr == '\t'
+        let scale = dat.scale;
+        if let Some(previous) = previous
         {
-            x += 8*4;
-            continue;
+            x += font.kerning(previous, chr)*scale;
         }
-        let i = if (chr as u8) >= 32 && (chr as u8) < 128 {chr as u32 - 31} else { 0 };
-        tex.set_alpha_mod(dat.color.3);
-        tex.set_color_mod(dat.color.0, dat.color.1, dat.color.2);
-        let sox = (i*8)%512;
-        let soy = ((i*8)/512)*16;
+        previous = Some(chr);
+        let glyph = match font.glyph(chr)
+        {
+            Some(glyph) => glyph,
+            None =>
+            {
+                x += font.advance(chr)*scale;
+                continue;
+            }
+        };
+        let advance = glyph.advance*scale+dat.letter_spacing;
+        let tex = match pages.get(glyph.page)
+        {
+            Some(tex) => tex,
+            None =>
+            {
+                x += advance;
+                continue;
+            }
+        };
+        let src = glyph.source;
+        let (gx, gy) = (pos.x+x+glyph.offset.x*scale, pos.y+y+glyph.offset.y*scale);
         let its = if dat.bold { 2 } else { 1 };
-        let crop = if dat.italic { 9 } else { 16 };
+        // The top part of an italic glyph is drawn a pixel to the right
+        let crop = if dat.italic { src.size.y*9/16 } else { src.size.y };
+        let (width, top, bottom) = ((src.size.x*scale) as u32, (crop*scale) as u32, ((src.size.y-crop)*scale) as u32);
         for it in 0..its
         {
-            render_copy_cut(canv, &tex, SRect::new(sox as i32, soy as i32, 8, crop), SRect::new(pos.x+x+it + if dat.italic { 1 } else { 0 }, pos.y+y, 8, crop), ren.cutout);
+            let shift = it*scale;
+            render_copy_cut(canv, &tex, SRect::new(src.pos.x, src.pos.y, src.size.x as u32, crop as u32), SRect::new(gx+shift + if dat.italic { scale } else { 0 }, gy, width, top), ren.cutout);
             if dat.italic
             {
-                render_copy_cut(canv, &tex, SRect::new(sox as i32, soy as i32+crop as i32, 8, 16-crop), SRect::new(pos.x+x+it, pos.y+crop as i32+y, 8, 16-crop), ren.cutout);
+                render_copy_cut(canv, &tex, SRect::new(src.pos.x, src.pos.y+crop, src.size.x as u32, (src.size.y-crop) as u32), SRect::new(gx+shift, gy+crop*scale, width, bottom), ren.cutout);
             }
         }
-        
-        x += dat.letter_spacing;
+
+        x += advance;
     }
 }
 
-
 pub fn main() {
-    let mut ui = xml_ui::parse_xml("./test.xml");
-
-    ui.propagate_styles_rec(ui.root, HashMap::new());
+    // Kept between reloads, so an unchanged document doesn't have to be matched again
+    let mut document = None;
+    let mut ui = ui::Item::build().into_ui();
+    xml_ui::reload_xml("./test.xml", "./style.css", &mut document, &mut ui);
 
     use std::fs::read_to_string;
     let config: Config = ron::from_str(
@@ -136,17 +233,20 @@ pub fn main() {
     let creator = canvas.texture_creator();
     
     let mut mouse_pos = (0, 0);
-    let font_img = sdl2::surface::Surface::load_bmp("font.bmp").unwrap();
-    let mut font_tex = font_img.as_texture(&creator).or(Err("Can't convert to texture")).unwrap();
+    let mut err: Option<String> = None;
+    let mut font_textures = load_font_textures(&creator, &ui.fonts).unwrap_or_else(|e| { err = Some(e); vec![] });
 
     canvas.set_draw_color(Color::RGB(0, 255, 255));
     canvas.clear();
     canvas.present();
     canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
     let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut err: Option<String> = None;
-    let mut frames = ui.calculate_layout();
-    frames.reverse();
+    // The window is the viewport of the layout, in logical pixels
+    let viewport = |canvas: &sdl2::render::WindowCanvas| {
+        let (w, h) = canvas.window().size();
+        Vec2f::new(w as f32, h as f32)
+    };
+    let mut frames = ui.calculate_layout(Some(viewport(&canvas)));
 
     let i = 0;
     'running: loop {
@@ -177,42 +277,77 @@ pub fn main() {
                 },
                 Event::KeyDown { keycode: Some(Keycode::R), .. } => {
                     err = None;
-                    ui = xml_ui::parse_xml("./test.xml");
-                    ui.propagate_styles_rec(ui.root, HashMap::new());
-                    frames = ui.calculate_layout();
-                    frames.reverse();
+                    xml_ui::reload_xml("./test.xml", "./style.css", &mut document, &mut ui);
+                    font_textures = load_font_textures(&creator, &ui.fonts).unwrap_or_else(|e| { err = Some(e); vec![] });
+                    frames = ui.calculate_layout(Some(viewport(&canvas)));
+                },
+                Event::Window { win_event: WindowEvent::Resized(..), .. } => {
+                    frames = ui.calculate_layout(Some(viewport(&canvas)));
+                },
+                Event::MouseWheel { x, y, .. } => {
+                    // Positive y scrolls up, towards the start of the content
+                    let delta = Vec2f::new(x as f32*SCROLL_STEP, -y as f32*SCROLL_STEP);
+                    if ui.scroll_at(&frames, Vec2f::new(mouse_pos.0 as f32, mouse_pos.1 as f32), delta) {
+                        frames = ui.calculate_layout(Some(viewport(&canvas)));
+                    }
                 },
                 _ => {}
             }
         }
         for i in frames.iter() {
             let elem = &match ui.things.get(i.for_id) { None => break, Some(x) => x };
-            let border = elem.get_border();
-            canvas.set_draw_color(Color::RGBA(border.1.0, border.1.1, border.1.2, border.1.3));
-            //canvas.fill_rect(sdl2::rect::Rect::new(i.rect.pos.x as i32-border.0 as i32, i.rect.pos.y as i32-border.0 as i32, i.rect.size.x as u32+(border.0*2.) as u32, i.rect.size.y as u32+(border.0*2.) as u32));
-            let offs = (wsize.0 as i32/2-frames[0].rect.size.x as i32/2, wsize.1 as i32/2-frames[0].rect.size.y as i32/2);
-            canvas.fill_rect(sdl2::rect::Rect::new(offs.0+i.rect.pos.x as i32-border.0 as i32, offs.1+i.rect.pos.y as i32-border.0 as i32, i.rect.size.x as u32+(border.0*1.) as u32, border.0 as u32)).unwrap();
-            canvas.fill_rect(sdl2::rect::Rect::new(offs.0+i.rect.pos.x as i32-border.0 as i32, offs.1+i.rect.pos.y as i32, border.0 as u32, i.rect.size.y as u32+(border.0*1.) as u32)).unwrap();
-            canvas.fill_rect(sdl2::rect::Rect::new(offs.0+i.rect.pos.x as i32 as i32, offs.1+i.rect.pos.y as i32+i.rect.size.y as i32, i.rect.size.x as u32+(border.0*1.) as u32, border.0 as u32)).unwrap();
-            canvas.fill_rect(sdl2::rect::Rect::new(offs.0+i.rect.pos.x as i32+i.rect.size.x as i32, offs.1+i.rect.pos.y as i32-border.0 as i32, border.0 as u32, i.rect.size.y as u32+(border.0*1.) as u32)).unwrap();
+            // Everything of the frame is clipped by the ancestors that clip their content
+            if i.clip.map_or(false, |e| e.size.x < 1. || e.size.y < 1.) {
+                continue;
+            }
+            let cutout = i.clip.map(to_srect);
+            canvas.set_clip_rect(cutout);
+            let (border, colors) = elem.get_border();
+            let (x, y) = (i.rect.pos.x as i32, i.rect.pos.y as i32);
+            let (w, h) = (i.rect.size.x as i32, i.rect.size.y as i32);
+            // Borders go outside of the frame, top and bottom ones cover the corners
+            let sides = [
+                (x-border.l as i32, y, border.l, h as f32),
+                (x-border.l as i32, y-border.t as i32, border.l+w as f32+border.r, border.t),
+                (x+w, y, border.r, h as f32),
+                (x-border.l as i32, y+h, border.l+w as f32+border.r, border.b),
+            ];
+            for ((sx, sy, sw, sh), color) in sides.iter().zip(colors.iter()) {
+                if *sw < 1. || *sh < 1. {
+                    continue;
+                }
+                canvas.set_draw_color(Color::RGBA(color.0, color.1, color.2, color.3));
+                canvas.fill_rect(sdl2::rect::Rect::new(*sx, *sy, *sw as u32, *sh as u32)).unwrap();
+            }
 
             match &elem.elem {
-                ui::Ui::Text { text } => {
+                ui::Ui::Text { .. } => {
                     let color = elem.get_fg();
-                    render_text(&mut canvas, &mut font_tex, &text, Vec2::new(i.rect.pos.x as i32+offs.0, i.rect.pos.y as i32+offs.1), &FontData {bold: true, italic: false, letter_spacing: 8, row_spacing: 16, color}, &RenderData { cutout: None });
+                    let style = elem.text_style(&ui.fonts);
+                    let font = ui.fonts.get(style.font);
+                    let pages = match font_textures.get_mut(style.font) { Some(pages) => pages, None => continue };
+                    let data = FontData { bold: style.bold, italic: style.italic, scale: style.scale, letter_spacing: style.letter_spacing, row_spacing: style.line_height, color };
+                    for run in i.runs.iter() {
+                        render_text(&mut canvas, pages, &run.text, Vec2::new(run.pos.x as i32, run.pos.y as i32), font, &data, &RenderData { cutout });
+                    }
                 },
                 ui::Ui::Button => {
                     canvas.set_draw_color(Color::RED);
-                    canvas.fill_rect(sdl2::rect::Rect::new(i.rect.pos.x as i32+offs.0, offs.1+i.rect.pos.y as i32, i.rect.size.x as u32, i.rect.size.y as u32)).unwrap();
+                    canvas.fill_rect(sdl2::rect::Rect::new(x, y, w as u32, h as u32)).unwrap();
                 },
                 ui::Ui::Div => {
                     let color = elem.get_bg();
                     canvas.set_draw_color(Color::RGBA(color.0, color.1, color.2, color.3));
-                    canvas.fill_rect(sdl2::rect::Rect::new(i.rect.pos.x as i32+offs.0, offs.1+i.rect.pos.y as i32, i.rect.size.x as u32, i.rect.size.y as u32)).unwrap();
+                    canvas.fill_rect(sdl2::rect::Rect::new(x, y, w as u32, h as u32)).unwrap();
                 },
                 //_ => {}
             }
         };
+        canvas.set_clip_rect(None);
+        // Scrollbars go over the content they scroll
+        for i in frames.iter() {
+            render_scrollbars(&mut canvas, i);
+        }
         // The rest of the game loop goes here...
         canvas.present();
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
@@ -11,20 +11,270 @@ pub struct Transform {
 }
 
 use std::collections::hash_map::*;
+use std::collections::HashSet;
+use std::cell::RefCell;
 
-pub type UiId = usize;
+pub type UiId = crate::bucket_array::Handle;
 
 #[derive(Debug)]
 pub struct UiElem {
     pub elem: Ui,
+    /// Declared styles, as they come from the stylesheet
     pub styles: HashMap<StyleRuleTag, Style>,
+    /// Filled by `UiSystem::compute_styles`, keywords are resolved and inherited values are filled in
+    computed: HashMap<StyleRuleTag, StyleRule>,
     pub parent: Option<UiId>,
     pub children: Vec<UiId>,
 }
 
+/// Sizes for each side of a box
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sides {
+    pub l: f32,
+    pub t: f32,
+    pub r: f32,
+    pub b: f32,
+}
+
+impl Sides {
+    pub fn all(v: f32) -> Self {
+        Self { l: v, t: v, r: v, b: v }
+    }
+
+    pub fn top_left(&self) -> Vec2f {
+        Vec2f::new(self.l, self.t)
+    }
+
+    /// Left and right together, top and bottom together
+    pub fn sum(&self) -> Vec2f {
+        Vec2f::new(self.l + self.r, self.t + self.b)
+    }
+}
+
+impl std::ops::Add for Sides {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Sides { l: self.l + other.l, t: self.t + other.t, r: self.r + other.r, b: self.b + other.b }
+    }
+}
+
+/// The box model of an element, frames of the layout are padding boxes so margin and border are outside of them
 pub struct ComputedStyle {
-    padding: Vec2f,
-    margin: Vec2f,
+    pub padding: Sides,
+    pub border: Sides,
+    pub margin: Sides,
+    pub width: Dimension,
+    pub height: Dimension,
+    pub min_width: Dimension,
+    pub min_height: Dimension,
+    pub max_width: Dimension,
+    pub max_height: Dimension,
+    pub box_sizing: BoxSizing,
+}
+
+impl ComputedStyle {
+    /// Everything between the frame and the outer edge of the element
+    pub fn outside(&self) -> Sides {
+        self.margin + self.border
+    }
+
+    /// Length of the frame along one axis for the given content length. Declared sizes and min/max
+    /// constraints take over the content, percentages are of `container`, the content length of the parent
+    pub fn frame_length(&self, horizontal: bool, content: f32, container: Option<f32>) -> f32 {
+        self.frame_length_as(horizontal, if horizontal { self.width } else { self.height }, content, container)
+    }
+
+    /// Same as `frame_length`, but with `size` declared instead of `width` or `height`
+    pub fn frame_length_as(&self, horizontal: bool, size: Dimension, content: f32, container: Option<f32>) -> f32 {
+        let (min, max, padding, border) = if horizontal {
+            (self.min_width, self.max_width, self.padding.sum().x, self.border.sum().x)
+        } else {
+            (self.min_height, self.max_height, self.padding.sum().y, self.border.sum().y)
+        };
+        let extra = match self.box_sizing {
+            BoxSizing::ContentBox => 0.,
+            BoxSizing::BorderBox => padding+border
+        };
+        let declared = |d: Dimension| d.resolve(container).map(|e| (e-extra).max(0.));
+        let mut length = declared(size).unwrap_or(content);
+        if let Some(max) = declared(max) {
+            length = length.min(max);
+        }
+        if let Some(min) = declared(min) {
+            length = length.max(min);
+        }
+        length+padding
+    }
+
+    /// Size of the content box if it doesn't depend on the content, children resolve percentages against it.
+    /// Stretched elements take their width from the container
+    fn definite_content(&self, container: (Option<f32>, Option<f32>), stretched: bool) -> (Option<f32>, Option<f32>) {
+        let padding = self.padding.sum();
+        let width = match self.width.resolve(container.0) {
+            Some(_) => Some(self.frame_length(true, 0., container.0)-padding.x),
+            None if stretched => container.0.map(|c| self.frame_length(true, c-self.outside().sum().x-padding.x, Some(c))-padding.x),
+            None => None
+        };
+        (width, self.height.resolve(container.1).map(|_| self.frame_length(false, 0., container.1)-padding.y))
+    }
+}
+
+/// Properties of an element with `display: flex`
+struct FlexContainer {
+    direction: FlexDirection,
+    wrap: FlexWrap,
+    justify: JustifyContent,
+    align: AlignItems,
+    row_gap: f32,
+    column_gap: f32,
+}
+
+impl FlexContainer {
+    fn is_row(&self) -> bool {
+        matches!(self.direction, FlexDirection::Row | FlexDirection::RowReverse)
+    }
+
+    /// Gaps between items in a line and between lines
+    fn gaps(&self) -> (f32, f32) {
+        if self.is_row() { (self.column_gap, self.row_gap) } else { (self.row_gap, self.column_gap) }
+    }
+}
+
+/// Items are clamped to a grid of this many tracks, so a huge line number or span can't grow the implicit grid without bounds
+const MAX_GRID_TRACKS: usize = 1000;
+
+/// Properties of an element with `display: grid`
+struct GridContainer {
+    columns: Vec<TrackSize>,
+    rows: Vec<TrackSize>,
+    areas: Vec<Vec<String>>,
+    row_gap: f32,
+    column_gap: f32,
+}
+
+impl GridContainer {
+    /// Columns and rows taken by a named area, start inclusive and end exclusive
+    fn area(&self, name: &str) -> Option<((usize, usize), (usize, usize))> {
+        let mut result: Option<((usize, usize), (usize, usize))> = None;
+        for (y, row) in self.areas.iter().enumerate() {
+            for x in row.iter().enumerate().filter(|e| e.1 == name).map(|e| e.0) {
+                let ((x0, x1), (y0, y1)) = result.unwrap_or(((x, x+1), (y, y+1)));
+                result = Some(((x0.min(x), x1.max(x+1)), (y0.min(y), y1.max(y+1))));
+            }
+        }
+        result
+    }
+
+    /// Start track and span of an item along one axis, the start is `None` if it has to be auto placed
+    fn resolve_lines(&self, start: &GridLine, end: &GridLine, columns: bool) -> (Option<usize>, usize) {
+        let explicit = if columns {
+            self.columns.len().max(self.areas.iter().map(|e| e.len()).max().unwrap_or(0))
+        } else {
+            self.rows.len().max(self.areas.len())
+        };
+        // Lines are numbered from 1, negative ones count from the end of the explicit grid
+        let line = |line: &GridLine, is_start: bool| match line {
+            GridLine::Line(n) if *n > 0 => Some(*n as usize-1),
+            GridLine::Line(n) if *n < 0 => Some((explicit as i32+1+*n).max(0) as usize),
+            GridLine::Area(name) => self.area(name).map(|(x, y)| {
+                let (from, to) = if columns { x } else { y };
+                if is_start { from } else { to }
+            }),
+            _ => None
+        };
+        let span = |line: &GridLine| match line {
+            GridLine::Span(n) => Some((*n).max(1) as usize),
+            _ => None
+        };
+        let line = |l: &GridLine, is_start: bool| line(l, is_start).map(|e| e.min(MAX_GRID_TRACKS-1));
+        let span = |l: &GridLine| span(l).map(|e| e.min(MAX_GRID_TRACKS));
+        let (start, span) = match (line(start, true), line(end, false)) {
+            (Some(s), Some(e)) if e > s => (Some(s), e-s),
+            (Some(s), Some(e)) if e < s => (Some(e), s-e),
+            (Some(s), _) => (Some(s), span(end).unwrap_or(1)),
+            (None, Some(e)) => {
+                let n = span(start).unwrap_or(1);
+                (Some(e.saturating_sub(n)), n.min(e.max(1)))
+            },
+            (None, None) => (None, span(start).or(span(end)).unwrap_or(1))
+        };
+        // The item has to end by the last line as well
+        (start, span.min(MAX_GRID_TRACKS-start.unwrap_or(0)).max(1))
+    }
+}
+
+/// Sizes tracks of one axis. `items` are the start track, span and outer size of every item,
+/// `available` is the size of the content box if it's known
+fn size_tracks(template: &[TrackSize], count: usize, gap: f32, available: Option<f32>, items: &[(usize, usize, f32)]) -> Vec<f32> {
+    let tracks: Vec<TrackSize> = (0..count).map(|i| template.get(i).copied().unwrap_or(TrackSize::Auto)).collect();
+    let gaps = gap*(count.max(1)-1) as f32;
+    // Tracks that grow with the content, fractions only do that while the free space is unknown
+    let flexible = |track: &TrackSize| match track {
+        TrackSize::Auto => true,
+        TrackSize::Fr(_) => available.is_none(),
+        TrackSize::Percent(_) => available.is_none(),
+        TrackSize::Px(_) => false
+    };
+    let mut sizes: Vec<f32> = tracks.iter().map(|track| match track {
+        TrackSize::Px(v) => *v,
+        TrackSize::Percent(p) => available.map_or(0., |e| e*p/100.),
+        _ => 0.
+    }).collect();
+
+    // Items spanning a single track go first, so wider ones only add what's missing
+    let mut items = items.to_vec();
+    items.sort_by_key(|e| e.1);
+    for (start, span, size) in items {
+        let range = start..(start+span).min(count);
+        let current: f32 = sizes[range.clone()].iter().sum::<f32>()+gap*(span-1) as f32;
+        let growing: Vec<usize> = range.filter(|i| flexible(&tracks[*i])).collect();
+        if size > current && !growing.is_empty() {
+            let share = (size-current)/growing.len() as f32;
+            for i in growing {
+                sizes[i] += share;
+            }
+        }
+    }
+
+    let fractions: f32 = tracks.iter().map(|e| if let TrackSize::Fr(f) = e { *f } else { 0. }).sum();
+    if fractions > 0. {
+        let unit = match available {
+            // The free space is shared, but a fraction doesn't get smaller than its content
+            Some(available) => {
+                let fixed: f32 = tracks.iter().zip(sizes.iter()).filter(|e| !matches!(e.0, TrackSize::Fr(_))).map(|e| e.1).sum();
+                (available-fixed-gaps).max(0.)/fractions
+            },
+            // Otherwise fractions keep their ratio, with the largest content deciding the unit
+            None => tracks.iter().zip(sizes.iter()).filter_map(|e| match e.0 {
+                TrackSize::Fr(f) if *f > 0. => Some(e.1/f),
+                _ => None
+            }).fold(0., f32::max)
+        };
+        for (track, size) in tracks.iter().zip(sizes.iter_mut()) {
+            if let TrackSize::Fr(f) = track {
+                *size = size.max(unit*f);
+            }
+        }
+    } else if let Some(available) = available {
+        // Auto tracks take the rest of the space
+        let autos = tracks.iter().filter(|e| **e == TrackSize::Auto).count();
+        let free = available-sizes.iter().sum::<f32>()-gaps;
+        if free > 0. && autos > 0 {
+            for (track, size) in tracks.iter().zip(sizes.iter_mut()) {
+                if *track == TrackSize::Auto {
+                    *size += free/autos as f32;
+                }
+            }
+        }
+    }
+    sizes
+}
+
+/// Properties of a child of a flex container
+struct FlexItem {
+    grow: f32,
+    shrink: f32,
+    basis: Dimension,
 }
 
 macro_rules! extract {
@@ -35,39 +285,156 @@ macro_rules! extract {
 
 
 impl UiElem {
-    fn get_style(&self, style: StyleRuleTag) -> Style {
-        *self.styles.get(&style).unwrap_or(&style.default())
+    /// The computed value, initial one if styles weren't computed yet
+    pub fn get_style(&self, style: StyleRuleTag) -> StyleRule {
+        self.computed.get(&style).cloned().unwrap_or_else(|| style.initial())
+    }
+
+    fn get_sides(&self, tags: [StyleRuleTag; 4]) -> Sides {
+        let [l, t, r, b] = tags.map(|tag| extract!(self.get_style(tag), StyleRule::Length(v) => v));
+        Sides { l, t, r, b }
+    }
+
+    /// Width of the border on each side, sides without a border style take no space
+    fn border_widths(&self) -> Sides {
+        let widths = self.get_sides(StyleRuleTag::BORDER_WIDTH);
+        let [l, t, r, b] = StyleRuleTag::BORDER_STYLE.map(|tag| extract!(self.get_style(tag), StyleRule::BorderStyle(s) => s));
+        let visible = |style: BorderStyle, width: f32| if style == BorderStyle::None { 0. } else { width };
+        Sides { l: visible(l, widths.l), t: visible(t, widths.t), r: visible(r, widths.r), b: visible(b, widths.b) }
+    }
+
+    fn get_dimension(&self, tag: StyleRuleTag) -> Dimension {
+        extract!(self.get_style(tag), StyleRule::Dimension(d) => d)
     }
 
     fn computed_style(&self) -> ComputedStyle {
-        let padding = self.get_style(StyleRuleTag::Padding).rule;
-        let margin = self.get_style(StyleRuleTag::Margin).rule;
-        let border = self.get_style(StyleRuleTag::Border).rule;
-        ComputedStyle { padding: extract!(padding, StyleRule::Offset{l, t, r: _, b: _} => Vec2f::new(l, t)),
-                        margin:  extract!(margin, StyleRule::Offset{l, t, r: _, b: _} => Vec2f::new(l, t))
-                                +extract!(border, StyleRule::Outline{ size, .. } => Vec2f::new(size, size)) }
+        ComputedStyle { padding: self.get_sides(StyleRuleTag::PADDING),
+                        border:  self.border_widths(),
+                        margin:  self.get_sides(StyleRuleTag::MARGIN),
+                        width: self.get_dimension(StyleRuleTag::Width),
+                        height: self.get_dimension(StyleRuleTag::Height),
+                        min_width: self.get_dimension(StyleRuleTag::MinWidth),
+                        min_height: self.get_dimension(StyleRuleTag::MinHeight),
+                        max_width: self.get_dimension(StyleRuleTag::MaxWidth),
+                        max_height: self.get_dimension(StyleRuleTag::MaxHeight),
+                        box_sizing: extract!(self.get_style(StyleRuleTag::BoxSizing), StyleRule::BoxSizing(b) => b) }
     }
 
     // TEMPORARY
 
     pub fn get_bg(&self) -> (u8, u8, u8, u8) {
-        extract!(self.get_style(StyleRuleTag::BackgroundColor).rule, StyleRule::Color { color } => color)
+        extract!(self.get_style(StyleRuleTag::BackgroundColor), StyleRule::Color { color } => color)
     }
 
-    pub fn get_border(&self) -> (f32, (u8, u8, u8, u8)) {
-        extract!(self.get_style(StyleRuleTag::Border).rule, StyleRule::Outline { size, color } => (size, color))
+    /// Widths and colors of the left, top, right and bottom borders
+    pub fn get_border(&self) -> (Sides, [(u8, u8, u8, u8); 4]) {
+        (self.border_widths(), StyleRuleTag::BORDER_COLOR.map(|tag| extract!(self.get_style(tag), StyleRule::Color { color } => color)))
     }
 
     pub fn get_fg(&self) -> (u8, u8, u8, u8) {
-        extract!(self.get_style(StyleRuleTag::Color).rule, StyleRule::Color { color } => color)
+        extract!(self.get_style(StyleRuleTag::Color), StyleRule::Color { color } => color)
+    }
+
+    fn flex_container(&self) -> FlexContainer {
+        FlexContainer {
+            direction: extract!(self.get_style(StyleRuleTag::FlexDirection), StyleRule::FlexDirection(d) => d),
+            wrap: extract!(self.get_style(StyleRuleTag::FlexWrap), StyleRule::FlexWrap(w) => w),
+            justify: extract!(self.get_style(StyleRuleTag::JustifyContent), StyleRule::JustifyContent(j) => j),
+            align: extract!(self.get_style(StyleRuleTag::AlignItems), StyleRule::AlignItems(a) => a),
+            row_gap: extract!(self.get_style(StyleRuleTag::RowGap), StyleRule::Length(v) => v),
+            column_gap: extract!(self.get_style(StyleRuleTag::ColumnGap), StyleRule::Length(v) => v),
+        }
+    }
+
+    fn grid_container(&self) -> GridContainer {
+        GridContainer {
+            columns: extract!(self.get_style(StyleRuleTag::GridTemplateColumns), StyleRule::Tracks(t) => t),
+            rows: extract!(self.get_style(StyleRuleTag::GridTemplateRows), StyleRule::Tracks(t) => t),
+            areas: extract!(self.get_style(StyleRuleTag::GridTemplateAreas), StyleRule::Areas(a) => a),
+            row_gap: extract!(self.get_style(StyleRuleTag::RowGap), StyleRule::Length(v) => v),
+            column_gap: extract!(self.get_style(StyleRuleTag::ColumnGap), StyleRule::Length(v) => v),
+        }
+    }
+
+    /// Start and end lines of the item along columns and rows
+    fn grid_item(&self) -> [GridLine; 4] {
+        [StyleRuleTag::GridColumnStart, StyleRuleTag::GridColumnEnd, StyleRuleTag::GridRowStart, StyleRuleTag::GridRowEnd]
+            .map(|tag| extract!(self.get_style(tag), StyleRule::GridLine(l) => l))
+    }
+
+    fn flex_item(&self) -> FlexItem {
+        FlexItem {
+            grow: extract!(self.get_style(StyleRuleTag::FlexGrow), StyleRule::Number(v) => v),
+            shrink: extract!(self.get_style(StyleRuleTag::FlexShrink), StyleRule::Number(v) => v),
+            basis: self.get_dimension(StyleRuleTag::FlexBasis),
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        extract!(self.get_style(StyleRuleTag::Position), StyleRule::Position(p) => p)
+    }
+
+    /// Absolutely positioned and fixed elements don't take space in the parent
+    pub fn is_out_of_flow(&self) -> bool {
+        matches!(self.position(), Position::Absolute | Position::Fixed)
+    }
+
+    /// Positioned elements with a z-index, and fixed ones, paint their descendants together
+    fn creates_stacking_context(&self) -> bool {
+        let z_index = extract!(self.get_style(StyleRuleTag::ZIndex), StyleRule::ZIndex(z) => z);
+        self.position() == Position::Fixed || (self.position() != Position::Static && z_index.is_some())
+    }
+
+    pub fn white_space(&self) -> WhiteSpace {
+        extract!(self.get_style(StyleRuleTag::WhiteSpace), StyleRule::WhiteSpace(w) => w)
+    }
+
+    pub fn word_break(&self) -> WordBreak {
+        extract!(self.get_style(StyleRuleTag::WordBreak), StyleRule::WordBreak(w) => w)
+    }
+
+    pub fn font_family(&self) -> Vec<String> {
+        extract!(self.get_style(StyleRuleTag::FontFamily), StyleRule::FontFamily(f) => f)
+    }
+
+    /// How text in the element is measured and drawn with `fonts`
+    pub fn text_style(&self, fonts: &Fonts) -> TextStyle {
+        let font = fonts.select(&self.font_family());
+        let size = fonts.get(font).line_height;
+        // Bitmap fonts are only scaled by whole numbers, `font-size: auto` and percentages are of the font's own size
+        let scale = self.get_dimension(StyleRuleTag::FontSize).resolve(Some(size as f32))
+            .map_or(1, |e| ((e/size as f32).round() as i32).max(1));
+        let line_height = match extract!(self.get_style(StyleRuleTag::LineHeight), StyleRule::LineHeight(l) => l) {
+            LineHeight::Normal => size*scale,
+            LineHeight::Px(v) => v.round() as i32,
+            LineHeight::Scale(v) => ((size*scale) as f32*v).round() as i32
+        };
+        TextStyle {
+            font,
+            scale,
+            bold: extract!(self.get_style(StyleRuleTag::FontWeight), StyleRule::FontWeight(w) => w) == FontWeight::Bold,
+            italic: extract!(self.get_style(StyleRuleTag::FontStyle), StyleRule::FontStyle(s) => s) == FontStyle::Italic,
+            letter_spacing: extract!(self.get_style(StyleRuleTag::LetterSpacing), StyleRule::Length(v) => v).round() as i32,
+            line_height
+        }
+    }
+
+    /// Overflow along the x and the y axis
+    pub fn overflow(&self) -> [Overflow; 2] {
+        [StyleRuleTag::OverflowX, StyleRuleTag::OverflowY].map(|tag| extract!(self.get_style(tag), StyleRule::Overflow(o) => o))
+    }
+
+    /// Both axes are clipped when one of them is, like `visible` turning into `auto` in CSS
+    pub fn clips_content(&self) -> bool {
+        self.overflow() != [Overflow::Visible; 2]
     }
 
     pub fn display(&self) -> DisplayType {
-        extract!(self.get_style(StyleRuleTag::Display).rule, StyleRule::Display(d) => d)   
+        extract!(self.get_style(StyleRuleTag::Display), StyleRule::Display(d) => d)   
     }
 
     pub fn stretch(&self) -> StretchType {
-        extract!(self.get_style(StyleRuleTag::Stretch).rule, StyleRule::Stretch(d) => d)   
+        extract!(self.get_style(StyleRuleTag::Stretch), StyleRule::Stretch(d) => d)   
     }
 }
 
@@ -81,9 +448,65 @@ pub struct Item {
 
 #[derive(Hash, Eq, PartialEq, Debug, Copy, Clone)]
 pub enum StyleRuleTag {
-    Padding,
-    Margin,
-    Border,
+    PaddingLeft,
+    PaddingTop,
+    PaddingRight,
+    PaddingBottom,
+    MarginLeft,
+    MarginTop,
+    MarginRight,
+    MarginBottom,
+    BorderLeftWidth,
+    BorderTopWidth,
+    BorderRightWidth,
+    BorderBottomWidth,
+    BorderLeftColor,
+    BorderTopColor,
+    BorderRightColor,
+    BorderBottomColor,
+    BorderLeftStyle,
+    BorderTopStyle,
+    BorderRightStyle,
+    BorderBottomStyle,
+    Width,
+    Height,
+    MinWidth,
+    MinHeight,
+    MaxWidth,
+    MaxHeight,
+    BoxSizing,
+    FlexDirection,
+    FlexWrap,
+    JustifyContent,
+    AlignItems,
+    FlexGrow,
+    FlexShrink,
+    FlexBasis,
+    RowGap,
+    ColumnGap,
+    GridTemplateColumns,
+    GridTemplateRows,
+    GridTemplateAreas,
+    GridColumnStart,
+    GridColumnEnd,
+    GridRowStart,
+    GridRowEnd,
+    Position,
+    Left,
+    Top,
+    Right,
+    Bottom,
+    ZIndex,
+    WhiteSpace,
+    WordBreak,
+    FontFamily,
+    FontWeight,
+    FontStyle,
+    FontSize,
+    LetterSpacing,
+    LineHeight,
+    OverflowX,
+    OverflowY,
     BackgroundColor,
     Color,
     Display,
@@ -91,13 +514,143 @@ pub enum StyleRuleTag {
 }
 
 impl StyleRuleTag {
+    pub const ALL: [StyleRuleTag; 63] = [
+        StyleRuleTag::PaddingLeft,
+        StyleRuleTag::PaddingTop,
+        StyleRuleTag::PaddingRight,
+        StyleRuleTag::PaddingBottom,
+        StyleRuleTag::MarginLeft,
+        StyleRuleTag::MarginTop,
+        StyleRuleTag::MarginRight,
+        StyleRuleTag::MarginBottom,
+        StyleRuleTag::BorderLeftWidth,
+        StyleRuleTag::BorderTopWidth,
+        StyleRuleTag::BorderRightWidth,
+        StyleRuleTag::BorderBottomWidth,
+        StyleRuleTag::BorderLeftColor,
+        StyleRuleTag::BorderTopColor,
+        StyleRuleTag::BorderRightColor,
+        StyleRuleTag::BorderBottomColor,
+        StyleRuleTag::BorderLeftStyle,
+        StyleRuleTag::BorderTopStyle,
+        StyleRuleTag::BorderRightStyle,
+        StyleRuleTag::BorderBottomStyle,
+        StyleRuleTag::Width,
+        StyleRuleTag::Height,
+        StyleRuleTag::MinWidth,
+        StyleRuleTag::MinHeight,
+        StyleRuleTag::MaxWidth,
+        StyleRuleTag::MaxHeight,
+        StyleRuleTag::BoxSizing,
+        StyleRuleTag::FlexDirection,
+        StyleRuleTag::FlexWrap,
+        StyleRuleTag::JustifyContent,
+        StyleRuleTag::AlignItems,
+        StyleRuleTag::FlexGrow,
+        StyleRuleTag::FlexShrink,
+        StyleRuleTag::FlexBasis,
+        StyleRuleTag::RowGap,
+        StyleRuleTag::ColumnGap,
+        StyleRuleTag::GridTemplateColumns,
+        StyleRuleTag::GridTemplateRows,
+        StyleRuleTag::GridTemplateAreas,
+        StyleRuleTag::GridColumnStart,
+        StyleRuleTag::GridColumnEnd,
+        StyleRuleTag::GridRowStart,
+        StyleRuleTag::GridRowEnd,
+        StyleRuleTag::Position,
+        StyleRuleTag::Left,
+        StyleRuleTag::Top,
+        StyleRuleTag::Right,
+        StyleRuleTag::Bottom,
+        StyleRuleTag::ZIndex,
+        StyleRuleTag::WhiteSpace,
+        StyleRuleTag::WordBreak,
+        StyleRuleTag::FontFamily,
+        StyleRuleTag::FontWeight,
+        StyleRuleTag::FontStyle,
+        StyleRuleTag::FontSize,
+        StyleRuleTag::LetterSpacing,
+        StyleRuleTag::LineHeight,
+        StyleRuleTag::OverflowX,
+        StyleRuleTag::OverflowY,
+        StyleRuleTag::BackgroundColor,
+        StyleRuleTag::Color,
+        StyleRuleTag::Display,
+        StyleRuleTag::Stretch
+    ];
+
+    // Per side longhands, in the left, top, right, bottom order of `Sides`
+    pub const PADDING: [StyleRuleTag; 4] = [StyleRuleTag::PaddingLeft, StyleRuleTag::PaddingTop, StyleRuleTag::PaddingRight, StyleRuleTag::PaddingBottom];
+    pub const MARGIN: [StyleRuleTag; 4] = [StyleRuleTag::MarginLeft, StyleRuleTag::MarginTop, StyleRuleTag::MarginRight, StyleRuleTag::MarginBottom];
+    pub const BORDER_WIDTH: [StyleRuleTag; 4] = [StyleRuleTag::BorderLeftWidth, StyleRuleTag::BorderTopWidth, StyleRuleTag::BorderRightWidth, StyleRuleTag::BorderBottomWidth];
+    pub const BORDER_COLOR: [StyleRuleTag; 4] = [StyleRuleTag::BorderLeftColor, StyleRuleTag::BorderTopColor, StyleRuleTag::BorderRightColor, StyleRuleTag::BorderBottomColor];
+    pub const BORDER_STYLE: [StyleRuleTag; 4] = [StyleRuleTag::BorderLeftStyle, StyleRuleTag::BorderTopStyle, StyleRuleTag::BorderRightStyle, StyleRuleTag::BorderBottomStyle];
+    pub const INSET: [StyleRuleTag; 4] = [StyleRuleTag::Left, StyleRuleTag::Top, StyleRuleTag::Right, StyleRuleTag::Bottom];
+
     pub fn name(self) -> &'static str {
         use StyleRuleTag::*;
 
         match self {
-            Padding => "padding",
-            Margin => "margin",
-            Border => "border",
+            PaddingLeft => "padding-left",
+            PaddingTop => "padding-top",
+            PaddingRight => "padding-right",
+            PaddingBottom => "padding-bottom",
+            MarginLeft => "margin-left",
+            MarginTop => "margin-top",
+            MarginRight => "margin-right",
+            MarginBottom => "margin-bottom",
+            BorderLeftWidth => "border-left-width",
+            BorderTopWidth => "border-top-width",
+            BorderRightWidth => "border-right-width",
+            BorderBottomWidth => "border-bottom-width",
+            BorderLeftColor => "border-left-color",
+            BorderTopColor => "border-top-color",
+            BorderRightColor => "border-right-color",
+            BorderBottomColor => "border-bottom-color",
+            BorderLeftStyle => "border-left-style",
+            BorderTopStyle => "border-top-style",
+            BorderRightStyle => "border-right-style",
+            BorderBottomStyle => "border-bottom-style",
+            Width => "width",
+            Height => "height",
+            MinWidth => "min-width",
+            MinHeight => "min-height",
+            MaxWidth => "max-width",
+            MaxHeight => "max-height",
+            BoxSizing => "box-sizing",
+            FlexDirection => "flex-direction",
+            FlexWrap => "flex-wrap",
+            JustifyContent => "justify-content",
+            AlignItems => "align-items",
+            FlexGrow => "flex-grow",
+            FlexShrink => "flex-shrink",
+            FlexBasis => "flex-basis",
+            RowGap => "row-gap",
+            ColumnGap => "column-gap",
+            GridTemplateColumns => "grid-template-columns",
+            GridTemplateRows => "grid-template-rows",
+            GridTemplateAreas => "grid-template-areas",
+            GridColumnStart => "grid-column-start",
+            GridColumnEnd => "grid-column-end",
+            GridRowStart => "grid-row-start",
+            GridRowEnd => "grid-row-end",
+            Position => "position",
+            Left => "left",
+            Top => "top",
+            Right => "right",
+            Bottom => "bottom",
+            ZIndex => "z-index",
+            WhiteSpace => "white-space",
+            WordBreak => "word-break",
+            FontFamily => "font-family",
+            FontWeight => "font-weight",
+            FontStyle => "font-style",
+            FontSize => "font-size",
+            LetterSpacing => "letter-spacing",
+            LineHeight => "line-height",
+            OverflowX => "overflow-x",
+            OverflowY => "overflow-y",
             BackgroundColor => "background-color",
             Color => "color",
             Display => "display",
@@ -105,33 +658,61 @@ impl StyleRuleTag {
         }
     }
 
+    /// Longhand properties only, shorthands are expanded by `css_gen`
     pub fn from(s: &str) -> Option<StyleRuleTag> {
-        use StyleRuleTag::*;
+        if s == "background" {
+            return Some(StyleRuleTag::BackgroundColor)
+        }
+        StyleRuleTag::ALL.iter().copied().find(|e| e.name() == s)
+    }
 
-        Some(match s {
-            "padding" => Padding,
-            "margin" => Margin,
-            "border" => Border,
-            "background" | "background-color" => BackgroundColor,
-            "color" => Color,
-            "display" => Display,
-            "stretch" => Stretch,
-            _ => return None
-        })
+    /// Whether children take the value of the parent when it's not declared
+    pub fn inherited(self) -> bool {
+        matches!(self, StyleRuleTag::Color | StyleRuleTag::WhiteSpace | StyleRuleTag::WordBreak | StyleRuleTag::FontFamily |
+                       StyleRuleTag::FontWeight | StyleRuleTag::FontStyle | StyleRuleTag::FontSize | StyleRuleTag::LetterSpacing | StyleRuleTag::LineHeight)
     }
 
-    pub fn default(self) -> Style {
+    pub fn initial(self) -> StyleRule {
         use StyleRuleTag::*;
 
-        Style { propagating: matches!(self, Color), rule: match self {
-            Padding => StyleRule::Offset { l: 0., t: 0., b: 0., r: 0. },
-            Margin => StyleRule::Offset { l: 0., t: 0., b: 0., r: 0. },
-            Border => StyleRule::Outline { size: 0.0, color: (0, 0, 0, 0) },
+        match self {
+            PaddingLeft | PaddingTop | PaddingRight | PaddingBottom => StyleRule::Length(0.),
+            MarginLeft | MarginTop | MarginRight | MarginBottom => StyleRule::Length(0.),
+            BorderLeftWidth | BorderTopWidth | BorderRightWidth | BorderBottomWidth => StyleRule::Length(0.),
+            BorderLeftColor | BorderTopColor | BorderRightColor | BorderBottomColor => StyleRule::Color { color: (0, 0, 0, 0) },
+            BorderLeftStyle | BorderTopStyle | BorderRightStyle | BorderBottomStyle => StyleRule::BorderStyle(BorderStyle::None),
+            Width | Height => StyleRule::Dimension(Dimension::Auto),
+            MinWidth | MinHeight => StyleRule::Dimension(Dimension::Px(0.)),
+            MaxWidth | MaxHeight => StyleRule::Dimension(Dimension::Auto),
+            BoxSizing => StyleRule::BoxSizing(crate::ui::BoxSizing::ContentBox),
+            FlexDirection => StyleRule::FlexDirection(crate::ui::FlexDirection::Row),
+            FlexWrap => StyleRule::FlexWrap(crate::ui::FlexWrap::NoWrap),
+            JustifyContent => StyleRule::JustifyContent(crate::ui::JustifyContent::FlexStart),
+            AlignItems => StyleRule::AlignItems(crate::ui::AlignItems::Stretch),
+            FlexGrow => StyleRule::Number(0.),
+            FlexShrink => StyleRule::Number(1.),
+            FlexBasis => StyleRule::Dimension(Dimension::Auto),
+            RowGap | ColumnGap => StyleRule::Length(0.),
+            GridTemplateColumns | GridTemplateRows => StyleRule::Tracks(vec![]),
+            GridTemplateAreas => StyleRule::Areas(vec![]),
+            GridColumnStart | GridColumnEnd | GridRowStart | GridRowEnd => StyleRule::GridLine(crate::ui::GridLine::Auto),
+            Position => StyleRule::Position(crate::ui::Position::Static),
+            Left | Top | Right | Bottom => StyleRule::Dimension(Dimension::Auto),
+            ZIndex => StyleRule::ZIndex(None),
+            WhiteSpace => StyleRule::WhiteSpace(crate::ui::WhiteSpace::Normal),
+            WordBreak => StyleRule::WordBreak(crate::ui::WordBreak::Normal),
+            FontFamily => StyleRule::FontFamily(vec![]),
+            FontWeight => StyleRule::FontWeight(crate::ui::FontWeight::Normal),
+            FontStyle => StyleRule::FontStyle(crate::ui::FontStyle::Normal),
+            FontSize => StyleRule::Dimension(Dimension::Auto),
+            LetterSpacing => StyleRule::Length(0.),
+            LineHeight => StyleRule::LineHeight(crate::ui::LineHeight::Normal),
+            OverflowX | OverflowY => StyleRule::Overflow(crate::ui::Overflow::Visible),
             BackgroundColor => StyleRule::Color { color: (255, 255, 255, 0) },
             Color => StyleRule::Color { color: (0, 0, 0, 255) },
             Display => StyleRule::Display(DisplayType::Block),
             Stretch => StyleRule::Stretch(StretchType::IfBlock)
-        }}
+        }
     }
 }
 
@@ -139,7 +720,150 @@ impl StyleRuleTag {
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum DisplayType {
     Inline,
-    Block
+    Block,
+    /// Block for the parent, children are laid out as flex items
+    Flex,
+    /// Block for the parent, children are placed on a grid
+    Grid
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Position {
+    Static,
+    /// Laid out in the flow, then moved by the offsets
+    Relative,
+    /// Taken out of the flow and placed in the nearest positioned ancestor
+    Absolute,
+    /// Taken out of the flow and placed in the viewport
+    Fixed
+}
+
+/// How spaces and newlines in text are treated, and whether lines are broken at the width of the container
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    /// Collapsed like `Normal`, but kept on one line
+    NoWrap,
+    /// Spaces and newlines are kept, lines are only broken at newlines
+    Pre,
+    /// Spaces and newlines are kept, lines are also broken to fit
+    PreWrap
+}
+
+impl WhiteSpace {
+    fn collapses(self) -> bool {
+        matches!(self, WhiteSpace::Normal | WhiteSpace::NoWrap)
+    }
+
+    fn wraps(self) -> bool {
+        matches!(self, WhiteSpace::Normal | WhiteSpace::PreWrap)
+    }
+}
+
+/// Where words that don't fit a line may be broken
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WordBreak {
+    /// Only between words, long words overflow
+    Normal,
+    /// Between any two characters
+    BreakAll,
+    /// Between any two characters, but only if the word doesn't fit on a line of its own
+    BreakWord
+}
+
+/// Bitmap fonts have no bold faces, bold text is drawn twice with an offset
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FontWeight {
+    Normal,
+    Bold
+}
+
+/// Italic text is slanted by drawing the top of the glyphs further right
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FontStyle {
+    Normal,
+    Italic
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineHeight {
+    /// The height of the font
+    Normal,
+    Px(f32),
+    /// Multiple of the height of the font
+    Scale(f32)
+}
+
+/// What happens to content that doesn't fit the padding box
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Overflow {
+    Visible,
+    /// Clipped, can't be scrolled by the user
+    Hidden,
+    /// Clipped, with a scrollbar even if everything fits
+    Scroll,
+    /// Clipped, with a scrollbar only when something doesn't fit
+    Auto
+}
+
+impl Overflow {
+    fn scrolls(self) -> bool {
+        matches!(self, Overflow::Scroll | Overflow::Auto)
+    }
+}
+
+/// Size of a column or a row of a grid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackSize {
+    Px(f32),
+    Percent(f32),
+    /// Share of the space left by the other tracks
+    Fr(f32),
+    /// As large as the content
+    Auto
+}
+
+/// One end of an item's placement on a grid
+#[derive(Debug, Clone, PartialEq)]
+pub enum GridLine {
+    Auto,
+    /// Number of the line, counted from 1
+    Line(i32),
+    Span(u32),
+    /// Start or end of a named area, depending on which end this is
+    Area(String)
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FlexDirection {
+    Row,
+    RowReverse,
+    Column,
+    ColumnReverse
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FlexWrap {
+    NoWrap,
+    Wrap
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum JustifyContent {
+    FlexStart,
+    FlexEnd,
+    Center,
+    SpaceBetween,
+    SpaceAround,
+    SpaceEvenly
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AlignItems {
+    FlexStart,
+    FlexEnd,
+    Center,
+    Stretch
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -149,11 +873,66 @@ pub enum StretchType {
     False
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BorderStyle {
+    /// The border takes no space, whatever its width is
+    None,
+    Solid
+}
+
+/// A declared size, `max-width: none` is `Auto` as well
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dimension {
+    Auto,
+    Px(f32),
+    Percent(f32)
+}
+
+impl Dimension {
+    /// `None` for `Auto` and for percentages when the length they refer to isn't known
+    pub fn resolve(self, of: Option<f32>) -> Option<f32> {
+        match self {
+            Dimension::Auto => None,
+            Dimension::Px(v) => Some(v),
+            Dimension::Percent(p) => of.map(|e| e*p/100.)
+        }
+    }
+}
+
+/// Which box `width` and `height` refer to
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BoxSizing {
+    ContentBox,
+    BorderBox
+}
+
+#[derive(Debug, Clone)]
 pub enum StyleRule {
-    Offset {l: f32, t: f32, r: f32, b: f32},
-    Outline {size: f32, color: (u8, u8, u8, u8)},
+    Length(f32),
+    Dimension(Dimension),
+    BoxSizing(BoxSizing),
+    Number(f32),
+    FlexDirection(FlexDirection),
+    FlexWrap(FlexWrap),
+    JustifyContent(JustifyContent),
+    AlignItems(AlignItems),
+    Tracks(Vec<TrackSize>),
+    /// Names of cells by row, `.` is an unnamed cell
+    Areas(Vec<Vec<String>>),
+    GridLine(GridLine),
+    Position(Position),
+    /// `None` is `auto`
+    ZIndex(Option<i32>),
+    WhiteSpace(WhiteSpace),
+    WordBreak(WordBreak),
+    /// Names in the order of preference
+    FontFamily(Vec<String>),
+    FontWeight(FontWeight),
+    FontStyle(FontStyle),
+    LineHeight(LineHeight),
+    Overflow(Overflow),
     Color {color: (u8, u8, u8, u8)},
+    BorderStyle(BorderStyle),
     Display(DisplayType),
     Stretch(StretchType)
 }
@@ -161,15 +940,28 @@ pub enum StyleRule {
 impl StyleRule {
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct Style {
-    pub rule: StyleRule,
-    pub propagating: bool
+/// A declared value, either a concrete one or one of the CSS-wide keywords
+#[derive(Debug, Clone)]
+pub enum Style {
+    Value(StyleRule),
+    /// Take the value of the parent
+    Inherit,
+    /// Take the initial value
+    Initial,
+    /// Inherit if the property is inherited, otherwise take the initial value
+    Unset
+}
+
+impl From<StyleRule> for Style {
+    fn from(rule: StyleRule) -> Self {
+        Style::Value(rule)
+    }
 }
 
 
 use crate::atoms::*;
 use crate::bucket_array::*;
+use crate::font::Fonts;
 
 impl Item {
 
@@ -179,87 +971,534 @@ impl Item {
     
     pub fn component(mut self, c: Ui) -> Self {
         if matches!(c, Ui::Text { .. }) {
-            self.styles.insert(StyleRuleTag::Display, Style { propagating: false, rule: StyleRule::Display(DisplayType::Inline) });
+            self.styles.insert(StyleRuleTag::Display, Style::Value(StyleRule::Display(DisplayType::Inline)));
         }
         self.elem = c;
         self
     }
 
-    pub fn style(mut self, tag: StyleRuleTag, style: StyleRule) -> Self {
-        let propagating =  matches!(tag, StyleRuleTag::Color);
-        self.styles.insert(tag, Style { rule: style, propagating });
+    pub fn style<S: Into<Style>>(mut self, tag: StyleRuleTag, style: S) -> Self {
+        self.styles.insert(tag, style.into());
         self
     }
 
-    pub fn padding(mut self, x: f32, y: f32) -> Self {
-        self.styles.insert(StyleRuleTag::Padding, Style { rule: StyleRule::Offset { l: x, t: y, r: x, b: y }, propagating: false });
+    /// Sets the four longhands of a side property, like `StyleRuleTag::PADDING`
+    pub fn sides(mut self, tags: [StyleRuleTag; 4], sides: Sides) -> Self {
+        for (tag, v) in tags.iter().zip([sides.l, sides.t, sides.r, sides.b].iter()) {
+            self.styles.insert(*tag, Style::Value(StyleRule::Length(*v)));
+        }
         self
     }
 
+    pub fn padding(self, x: f32, y: f32) -> Self {
+        self.sides(StyleRuleTag::PADDING, Sides { l: x, t: y, r: x, b: y })
+    }
+
     pub fn with_children(mut self, v: Vec<Item>) -> Self {
         self.children = v;
         self
     }
 
     fn into_elem(mut self, things: &mut BucketArray<UiElem>, parent: Option<UiId>) -> UiId {
-        let o = UiElem { elem: self.elem, styles: self.styles, children: vec![], parent: parent};
+        let o = UiElem { elem: self.elem, styles: self.styles, computed: HashMap::new(), children: vec![], parent: parent};
         let mut children: Vec<_> = self.children.drain(..).map(|e| e.into_elem(things, None)).collect();
         let pid = things.insert(o);
         for child in children.iter() {
             things.get_mut(*child).unwrap().parent = Some(pid);
         }
-        things.get_mut(pid).unwrap().children = children.drain(..).collect();
+        things.get_mut(pid).unwrap().children = std::mem::take(&mut children);
         pid
     }
 
+    /// Styles of the result are already computed
     pub fn into_ui(self) -> UiSystem {
         let mut arr = BucketArray::new();
-        UiSystem { 
+        let mut sys = UiSystem { 
             root: self.into_elem(&mut arr, None),
-            things: arr
-        }
+            things: arr,
+            fonts: Fonts::new(),
+            scroll: HashMap::new(),
+            layout_cache: RefCell::new(HashMap::new())
+        };
+        sys.compute_styles();
+        sys
     }
 }
 
 
 pub struct UiSystem {
     pub things: BucketArray<UiElem>,
-    pub root: UiId
+    pub root: UiId,
+    /// Text is measured with them in layout and drawn with them
+    pub fonts: Fonts,
+    /// How far the content of scroll containers is scrolled, layout keeps the offsets in range
+    pub scroll: HashMap<UiId, Vec2f>,
+    /// Layout of the elements that didn't change since the last `calculate_layout`, an element is dirty when it has no entry.
+    /// Filled while laying out, which only needs a shared reference
+    layout_cache: RefCell<HashMap<UiId, CachedLayout>>
+}
+
+/// Computed font properties of an element, in whole pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextStyle {
+    /// Index in `UiSystem::fonts`
+    pub font: usize,
+    /// Glyphs are drawn this many times larger
+    pub scale: i32,
+    pub bold: bool,
+    pub italic: bool,
+    /// Added after every drawn glyph
+    pub letter_spacing: i32,
+    pub line_height: i32
+}
+
+impl TextStyle {
+    /// Width of the text on one line, `font` has to be the one at `self.font`
+    pub fn measure(&self, font: &crate::font::Font, text: &str) -> i32 {
+        let drawn = text.chars().filter(|e| font.glyph(*e).is_some()).count() as i32;
+        font.measure(text)*self.scale+drawn*self.letter_spacing
+    }
+}
+
+/// Text split at break opportunities
+#[derive(Debug, PartialEq)]
+enum TextPiece<'a> {
+    Word(&'a str),
+    Space(&'a str),
+    Newline
+}
+
+/// Collapsing turns every run of whitespace, newlines included, into a single space
+fn split_text(text: &str, white_space: WhiteSpace) -> Vec<TextPiece<'_>> {
+    let mut pieces = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (piece, len) = if c == '\n' && !white_space.collapses() {
+            (TextPiece::Newline, 1)
+        } else if c.is_whitespace() {
+            let len = rest.find(|e: char| !e.is_whitespace() || (e == '\n' && !white_space.collapses())).unwrap_or(rest.len());
+            (TextPiece::Space(if white_space.collapses() { " " } else { &rest[..len] }), len)
+        } else {
+            let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            (TextPiece::Word(&rest[..len]), len)
+        };
+        pieces.push(piece);
+        rest = &rest[len..];
+    }
+    pieces
+}
+
+/// Lines of an inline formatting context while they are being filled
+struct LineBox {
+    /// Corner of the first line
+    origin: Vec2f,
+    /// Width lines are broken at, `None` if they never are
+    limit: Option<f32>,
+    x: f32,
+    y: f32,
+    height: f32,
+    /// Width of the widest finished line
+    width: f32,
+    /// Counts lines, so text can tell if it is still on the same one
+    index: usize,
+    /// Nothing that takes a place was put on the line yet
+    empty: bool,
+    /// Width of a collapsed space that waits for something to follow it on the same line
+    space: f32,
+    /// Area covered by the content of every inline box that is open
+    open: Vec<Option<Rectf>>,
+}
+
+impl LineBox {
+    fn new(origin: Vec2f, limit: Option<f32>) -> Self {
+        Self { origin, limit, x: 0., y: 0., height: 0., width: 0., index: 0, empty: true, space: 0., open: vec![] }
+    }
+
+    fn pos(&self) -> Vec2f {
+        self.origin+Vec2f::new(self.x, self.y)
+    }
+
+    fn pending_space(&self) -> f32 {
+        if self.empty { 0. } else { self.space }
+    }
+
+    fn overflows(&self, width: f32) -> bool {
+        self.limit.is_some_and(|limit| self.x+self.pending_space()+width > limit)
+    }
+
+    /// Whether something this wide can go on the line, there is always place on an empty one
+    fn fits(&self, width: f32) -> bool {
+        self.empty || !self.overflows(width)
+    }
+
+    fn new_line(&mut self) {
+        self.width = self.width.max(self.x);
+        self.y += self.height;
+        self.x = 0.;
+        self.height = 0.;
+        self.index += 1;
+        self.empty = true;
+        self.space = 0.;
+    }
+
+    /// Takes up place on the line after the pending space, open inline boxes grow to cover it
+    fn put(&mut self, width: f32, height: f32) -> Rectf {
+        self.x += self.pending_space();
+        let rect = Rect { pos: self.pos(), size: Vec2f::new(width, height) };
+        self.x += width;
+        self.height = self.height.max(height);
+        self.empty = false;
+        self.space = 0.;
+        self.cover(rect);
+        rect
+    }
+
+    fn cover(&mut self, rect: Rectf) {
+        for area in self.open.iter_mut() {
+            *area = Some(area.map_or(rect, |e| e.union(rect)));
+        }
+    }
+
+    /// Size of all lines so far
+    fn size(&self) -> Vec2f {
+        Vec2f::new(self.width.max(self.x), self.y+self.height)
+    }
+}
+
+/// Adjoining vertical margins, they collapse into the largest positive one plus the most negative one
+#[derive(Debug, Clone, Copy, Default)]
+struct CollapsedMargin {
+    positive: f32,
+    negative: f32
+}
+
+impl CollapsedMargin {
+    fn new(margin: f32) -> Self {
+        Self { positive: margin.max(0.), negative: margin.min(0.) }
+    }
+
+    fn join(self, other: Self) -> Self {
+        Self { positive: self.positive.max(other.positive), negative: self.negative.min(other.negative) }
+    }
+
+    fn value(self) -> f32 {
+        self.positive+self.negative
+    }
+}
+
+/// Layout of an element from the last time it was laid out, reused until something in its subtree changes.
+/// Elements laid out from inside of it have entries of their own, the subtree is put back together from them
+struct CachedLayout {
+    offset: Vec2f,
+    container: (Option<f32>, Option<f32>),
+    outer: Vec2f,
+    /// Frames pushed by the element itself, like the ones of its text, the frame of the element is the last one
+    frames: Vec<Frame>,
+    /// Elements laid out from inside of this one, with the offset they were laid out at and their frame
+    /// once this one was done, the element moves and resizes them in between
+    nested: Vec<(UiId, Vec2f, Rectf)>,
+    /// Sizes of the nested flex and grid items before they were moved into place
+    hypothetical: Vec<(UiId, Vec2f)>,
+    margins: Option<(CollapsedMargin, CollapsedMargin, bool)>,
 }
 
 struct LayoutCalculator<'a> {
     sys: &'a UiSystem,
-    result: Vec<Frame>
+    result: Vec<Frame>,
+    /// Place of the frame of every element in `result`
+    index: HashMap<UiId, usize>,
+    /// Frame sizes of flex items before flexing, items are flexed again once the container is stretched
+    hypothetical: HashMap<UiId, Vec2f>,
+    /// Top and bottom margins of elements after collapsing with the margins of their children,
+    /// and whether the two collapse together through an empty element
+    margins: HashMap<UiId, (CollapsedMargin, CollapsedMargin, bool)>,
+    /// Size of the window, the containing block of the root and of fixed elements
+    viewport: Option<Vec2f>,
+    /// Elements laid out so far from inside of each element that is being laid out, with their offsets
+    /// and the frames they pushed
+    nested: Vec<Vec<(UiId, Vec2f, std::ops::Range<usize>)>>,
 }
 
+#[derive(Debug, Clone)]
 pub struct Frame {
     pub rect: Rectf,
+    /// Place in the paint order, frames are painted from the lowest one
     pub zindex: usize,
     pub for_id: UiId,
+    /// Lines of a text element, empty for everything else
+    pub runs: Vec<TextRun>,
+    /// Area the frame is visible in, `None` if no ancestor clips it
+    pub clip: Option<Rectf>,
+    pub scroll: Option<ScrollArea>,
+}
+
+impl Frame {
+    fn translate(&mut self, delta: Vec2f) {
+        self.rect.pos = self.rect.pos+delta;
+        for run in self.runs.iter_mut() {
+            run.pos = run.pos+delta;
+        }
+    }
+}
+
+/// Scrolling state of an element that clips its content
+#[derive(Debug, Clone, Copy)]
+pub struct ScrollArea {
+    /// Size of the content from the corner of the frame, at least the size of the frame
+    pub content: Vec2f,
+    pub offset: Vec2f,
+    /// Whether the user can scroll along the x and the y axis
+    pub scrolls: [bool; 2],
+    /// Whether scrollbars are drawn along the x and the y axis
+    pub bars: [bool; 2],
+}
+
+/// Part of a text element that ended up on a single line
+#[derive(Debug, Clone)]
+pub struct TextRun {
+    /// Where the glyphs start, below the top of the line by half of the leading
+    pub pos: Vec2f,
+    pub text: String,
 }
 
 impl<'a> LayoutCalculator<'a> {
-    fn new(uisys: &'a UiSystem) -> Self {
-        Self { sys: uisys, result: vec![] }
+    fn new(uisys: &'a UiSystem, viewport: Option<Vec2f>) -> Self {
+        Self { sys: uisys, result: vec![], index: HashMap::new(), hypothetical: HashMap::new(), margins: HashMap::new(), viewport, nested: vec![] }
+    }
+
+    /// Containing block of the root, the viewport if there is one, otherwise the root itself
+    fn initial_block(&self) -> Rectf {
+        match self.viewport {
+            Some(size) => Rect { pos: Vec2f::new(0., 0.), size },
+            None => self.frame(self.sys.root).rect
+        }
     }
 
     fn calculate_layout(&mut self) -> Vec<Frame> {
-        self.diverge(self.sys.root, Vec2f::new(0., 0.), 0);
-        self.resize_children(self.sys.root);
-        self.result.drain(..).collect()
+        let root = self.sys.root;
+        self.diverge(root, Vec2f::new(0., 0.), (self.viewport.map(|e| e.x), self.viewport.map(|e| e.y)));
+        // The root has no parent to be stretched by
+        if let Some(viewport) = self.viewport.filter(|_| self.sys.is_stretched(root)) {
+            let computed = self.sys.things.get(root).unwrap().computed_style();
+            if computed.width == Dimension::Auto {
+                let available = viewport.x-computed.outside().sum().x-computed.padding.sum().x;
+                self.frame_mut(root).rect.size.x = computed.frame_length(true, available, Some(viewport.x));
+            }
+        }
+        self.resize_children(root);
+        let initial = self.initial_block();
+        self.place_positioned(root, initial);
+        self.scroll_containers();
+
+        // Frames come out back to front
+        let mut order = vec![];
+        self.sys.paint_order(root, &mut order);
+        let order: HashMap<UiId, usize> = order.into_iter().enumerate().map(|(n, id)| (id, n)).collect();
+        let mut result: Vec<Frame> = self.result.drain(..).collect();
+        for frame in result.iter_mut() {
+            frame.zindex = order[&frame.for_id];
+        }
+        result.sort_by_key(|e| e.zindex);
+        result
+    }
+
+    /// Moves the content of elements that clip it by their scroll offsets, then finds the area every frame is visible in
+    fn scroll_containers(&mut self) {
+        let sys = self.sys;
+        // Extents are measured before anything is scrolled, nested containers move together with their content
+        let containers: Vec<(UiId, Rectf)> = self.result.iter()
+            .filter(|e| sys.things.get(e.for_id).unwrap().clips_content())
+            .map(|e| (e.for_id, self.overflow_extent(e.for_id, sys.things.get(e.for_id).unwrap().position() != Position::Static)))
+            .collect();
+        for (id, extent) in containers {
+            let elem = sys.things.get(id).unwrap();
+            let frame = self.frame(id).rect;
+            // Content above or left of the frame can't be scrolled to
+            let content = Vec2f::new((extent.pos.x+extent.size.x-frame.pos.x).max(frame.size.x), (extent.pos.y+extent.size.y-frame.pos.y).max(frame.size.y));
+            let overflow = elem.overflow();
+            let wanted = sys.scroll.get(&id).copied().unwrap_or(Vec2f::new(0., 0.));
+            let offset = Vec2f::new(
+                if overflow[0] == Overflow::Visible { 0. } else { wanted.x.min(content.x-frame.size.x).max(0.) },
+                if overflow[1] == Overflow::Visible { 0. } else { wanted.y.min(content.y-frame.size.y).max(0.) },
+            );
+            for child in elem.children.iter() {
+                if sys.things.get(*child).unwrap().position() != Position::Fixed {
+                    self.shift(*child, -offset);
+                }
+            }
+            let fits = [content.x <= frame.size.x, content.y <= frame.size.y];
+            let bars = [0, 1].map(|i| overflow[i] == Overflow::Scroll || (overflow[i] == Overflow::Auto && !fits[i]));
+            self.frame_mut(id).scroll = Some(ScrollArea { content, offset, scrolls: overflow.map(Overflow::scrolls), bars });
+        }
+        let clips: Vec<(UiId, Option<Rectf>)> = self.result.iter().map(|e| (e.for_id, self.clip_of(e.for_id))).collect();
+        for (id, clip) in clips {
+            self.frame_mut(id).clip = clip;
+        }
+    }
+
+    /// Area covered by the content of an element, including its own frame. Content of nested elements
+    /// that clip it is left out, it only shows inside of them. Absolutely positioned descendants only
+    /// count when `positioned`, when their containing block is inside of the element
+    fn overflow_extent(&self, id: UiId, positioned: bool) -> Rectf {
+        let mut extent = self.frame(id).rect;
+        for child in self.sys.things.get(id).unwrap().children.iter() {
+            let elem = self.sys.things.get(*child).unwrap();
+            match elem.position() {
+                Position::Fixed => continue,
+                Position::Absolute if !positioned => continue,
+                _ => {}
+            }
+            let border = elem.computed_style().border;
+            let rect = self.frame(*child).rect;
+            extent = extent.union(Rect { pos: rect.pos-border.top_left(), size: rect.size+border.sum() });
+            if !elem.clips_content() {
+                extent = extent.union(self.overflow_extent(*child, positioned || elem.position() != Position::Static));
+            }
+        }
+        extent
+    }
+
+    /// Intersection of the frames of the ancestors that clip the element. Absolutely positioned elements
+    /// are only clipped from their containing block up, fixed ones by nothing
+    fn clip_of(&self, id: UiId) -> Option<Rectf> {
+        let mut clip: Option<Rectf> = None;
+        // Set while going through ancestors below the containing block of an absolutely positioned element
+        let mut skipping = false;
+        let mut current = self.sys.things.get(id).unwrap();
+        while let Some(parent) = current.parent {
+            match current.position() {
+                Position::Fixed => break,
+                Position::Absolute => skipping = true,
+                _ => {}
+            }
+            current = self.sys.things.get(parent).unwrap();
+            if current.position() != Position::Static {
+                skipping = false;
+            }
+            if !skipping && current.clips_content() {
+                let rect = self.frame(parent).rect;
+                clip = Some(clip.map_or(rect, |e| e.intersection(rect)));
+            }
+        }
+        clip
+    }
+
+    /// Moves relatively positioned elements by their offsets and places absolutely positioned ones
+    /// in their containing block, `containing` is the padding box of the nearest positioned ancestor
+    fn place_positioned(&mut self, id: UiId, containing: Rectf) {
+        let elem = self.sys.things.get(id).unwrap();
+        let computed = elem.computed_style();
+        let [left, top, right, bottom] = StyleRuleTag::INSET.map(|tag| elem.get_dimension(tag));
+        match elem.position() {
+            Position::Static => {},
+            Position::Relative => {
+                let (width, height) = (Some(containing.size.x), Some(containing.size.y));
+                let dx = left.resolve(width).or_else(|| right.resolve(width).map(|e| -e)).unwrap_or(0.);
+                let dy = top.resolve(height).or_else(|| bottom.resolve(height).map(|e| -e)).unwrap_or(0.);
+                self.shift(id, Vec2f::new(dx, dy));
+            },
+            position => {
+                let block = if position == Position::Fixed { self.initial_block() } else { containing };
+                let frame = self.frame(id).rect;
+                let outside = computed.outside();
+                let padding = computed.padding.sum();
+                // With both ends set an auto size fills the space between them, otherwise it fits the content
+                let place = |horizontal: bool, start: Dimension, end: Dimension| {
+                    let (block_pos, block_size, pos, size, declared, outside_start, outside_sum, padding) = if horizontal {
+                        (block.pos.x, block.size.x, frame.pos.x, frame.size.x, computed.width, outside.l, outside.sum().x, padding.x)
+                    } else {
+                        (block.pos.y, block.size.y, frame.pos.y, frame.size.y, computed.height, outside.t, outside.sum().y, padding.y)
+                    };
+                    let (start, end) = (start.resolve(Some(block_size)), end.resolve(Some(block_size)));
+                    let size = match (declared, start, end) {
+                        (Dimension::Auto, Some(start), Some(end)) => computed.frame_length(horizontal, (block_size-start-end-outside_sum-padding).max(0.), Some(block_size)),
+                        _ => computed.frame_length(horizontal, size-padding, Some(block_size))
+                    };
+                    let pos = match (start, end) {
+                        (Some(start), _) => block_pos+start+outside_start,
+                        (None, Some(end)) => block_pos+block_size-end-size-outside_sum+outside_start,
+                        // Stays where it would be in the flow
+                        (None, None) => pos
+                    };
+                    (pos, size)
+                };
+                let (x, width) = place(true, left, right);
+                let (y, height) = place(false, top, bottom);
+                self.shift(id, Vec2f::new(x, y)-frame.pos);
+                self.frame_mut(id).rect.size = Vec2f::new(width, height);
+                // Descendants are stretched again for the new size
+                self.resize_children(id);
+            }
+        }
+        let containing = if elem.position() == Position::Static { containing } else { self.frame(id).rect };
+        for i in elem.children.iter() {
+            self.place_positioned(*i, containing);
+        }
+    }
+
+    /// Frame of an element that has been laid out. Every pass after `diverge` walks the same tree that
+    /// `diverge` pushed a frame for each element of, so a missing frame is a bug in the calculator
+    fn frame(&self, id: UiId) -> &Frame {
+        let index = *self.index.get(&id).expect("frame looked up before its element was laid out");
+        &self.result[index]
+    }
+
+    fn frame_mut(&mut self, id: UiId) -> &mut Frame {
+        let index = *self.index.get(&id).expect("frame looked up before its element was laid out");
+        &mut self.result[index]
+    }
+
+    fn push(&mut self, frame: Frame) {
+        self.index.insert(frame.for_id, self.result.len());
+        self.result.push(frame);
+    }
+
+    /// Moves the frames of the element and all of its descendants
+    fn shift(&mut self, id: UiId, delta: Vec2f) {
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(n) = self.index.get(&id) {
+                self.result[*n].translate(delta);
+            }
+            stack.extend(self.sys.things.get(id).unwrap().children.iter().copied());
+        }
     }
 
     fn resize_children(&mut self, id: UiId) {
-        let parent_frame_rect = self.result.iter().find(|e| e.for_id == id).unwrap().rect;
+        let parent_frame_rect = self.frame(id).rect;
         let thiselem = self.sys.things.get(id).unwrap();
+        let padding = thiselem.computed_style().padding;
+        let parent_content = parent_frame_rect.size.x-padding.sum().x;
 
-        for i in self.result.iter_mut() {
-            if self.sys.things.get(i.for_id).unwrap().parent == Some(id) {
-                let elem = self.sys.things.get(i.for_id).unwrap();
-                match (elem.stretch(), elem.display()) {
-                    (StretchType::IfBlock, DisplayType::Block) | (StretchType::True, DisplayType::Block) => i.rect.size.x = parent_frame_rect.size.x-thiselem.computed_style().padding.x*2.-elem.computed_style().margin.x*2.0,
-                    _ => {}
-                }
+        let content = parent_frame_rect.size-padding.sum();
+        match thiselem.display() {
+            DisplayType::Flex => {
+                let flex = thiselem.flex_container();
+                let (main, cross) = if flex.is_row() { (content.x, content.y) } else { (content.y, content.x) };
+                self.arrange_flex(id, parent_frame_rect.pos+padding.top_left(), main, Some(cross));
+            },
+            DisplayType::Grid => {
+                self.arrange_grid(id, parent_frame_rect.pos+padding.top_left(), (Some(content.x), Some(content.y)));
+            },
+            _ => {}
+        }
+        let lays_out_children = matches!(thiselem.display(), DisplayType::Flex | DisplayType::Grid);
+
+        let sys = self.sys;
+        for child in thiselem.children.iter().copied() {
+            let elem = sys.things.get(child).unwrap();
+            let computed = elem.computed_style();
+            let frame = self.frame_mut(child);
+            match computed.width {
+                Dimension::Auto if sys.is_stretched(child) => {
+                    let available = parent_content-computed.outside().sum().x-computed.padding.sum().x;
+                    frame.rect.size.x = computed.frame_length(true, available, Some(parent_content));
+                },
+                // Width of the parent is only known now if it was stretched, flex and grid items are sized by the container
+                Dimension::Percent(_) if !lays_out_children && !elem.is_out_of_flow() => {
+                    frame.rect.size.x = computed.frame_length(true, 0., Some(parent_content))
+                },
+                _ => {}
             }
         }
 
@@ -268,77 +1507,1202 @@ impl<'a> LayoutCalculator<'a> {
         }
     }
 
-    fn diverge(&mut self, id: UiId, offset: Vec2f, depth: usize) -> Vec2f {
+    /// Frame length of a flex item along the main axis before it's flexed
+    fn flex_base(&self, id: UiId, horizontal: bool, container_main: Option<f32>) -> f32 {
+        let elem = self.sys.things.get(id).unwrap();
+        let computed = elem.computed_style();
+        let size = self.hypothetical[&id];
+        match elem.flex_item().basis {
+            Dimension::Auto => if horizontal { size.x } else { size.y },
+            basis => {
+                let content = if horizontal { size.x-computed.padding.sum().x } else { size.y-computed.padding.sum().y };
+                computed.frame_length_as(horizontal, basis, content, container_main)
+            }
+        }
+    }
+
+    /// Sizes and places the children of a flex container, `origin` is the corner of its content box.
+    /// `cross` is the size of the content box along the cross axis if it's already known.
+    /// Returns the size taken along the cross axis
+    fn arrange_flex(&mut self, id: UiId, origin: Vec2f, main: f32, cross: Option<f32>) -> f32 {
+        let sys = self.sys;
+        let flex = sys.things.get(id).unwrap().flex_container();
+        let row = flex.is_row();
+        let reverse = matches!(flex.direction, FlexDirection::RowReverse | FlexDirection::ColumnReverse);
+        let (main_gap, cross_gap) = flex.gaps();
+        // Main axis component first, cross axis second
+        let split = |v: Vec2f| if row { (v.x, v.y) } else { (v.y, v.x) };
+        let join = |m: f32, c: f32| if row { Vec2f::new(m, c) } else { Vec2f::new(c, m) };
+
+        struct Line { items: Vec<(UiId, ComputedStyle, FlexItem)>, sizes: Vec<(f32, f32)> }
+        let mut lines: Vec<Line> = vec![];
+        let mut used = 0.;
+        for child in sys.in_flow_children(id) {
+            let elem = sys.things.get(child).unwrap();
+            let computed = elem.computed_style();
+            let base = self.flex_base(child, row, Some(main));
+            let (_, hypothetical_cross) = split(self.hypothetical[&child]);
+            let outer = base+split(computed.outside().sum()).0;
+            let starts_line = match lines.last() {
+                None => true,
+                Some(_) => flex.wrap == FlexWrap::Wrap && used+main_gap+outer > main
+            };
+            if starts_line {
+                lines.push(Line { items: vec![], sizes: vec![] });
+                used = outer;
+            } else {
+                used += main_gap+outer;
+            }
+            let line = lines.last_mut().unwrap();
+            line.items.push((child, computed, elem.flex_item()));
+            line.sizes.push((base, hypothetical_cross));
+        }
+
+        let single_line = lines.len() == 1;
+        let mut line_start = 0.;
+        for (n, mut line) in lines.into_iter().enumerate() {
+            if n > 0 {
+                line_start += cross_gap;
+            }
+            let outside: Vec<(f32, f32)> = line.items.iter().map(|e| split(e.1.outside().sum())).collect();
+            let count = line.items.len() as f32;
+            let free = |sizes: &[(f32, f32)]| main-sizes.iter().zip(outside.iter()).map(|(s, o)| s.0+o.0).sum::<f32>()-main_gap*(count-1.);
+
+            // Grow or shrink the items to fill the line, then keep them within min and max sizes
+            let initial_free = free(&line.sizes);
+            let grow: f32 = line.items.iter().map(|e| e.2.grow).sum();
+            let shrink: f32 = line.items.iter().zip(line.sizes.iter()).map(|(e, s)| e.2.shrink*s.0).sum();
+            for ((_, computed, item), size) in line.items.iter().zip(line.sizes.iter_mut()) {
+                let flexed = if initial_free > 0. && grow > 0. {
+                    size.0+initial_free*item.grow/grow
+                } else if initial_free < 0. && shrink > 0. {
+                    size.0+initial_free*item.shrink*size.0/shrink
+                } else {
+                    continue
+                };
+                let padding = split(computed.padding.sum()).0;
+                size.0 = computed.frame_length_as(row, Dimension::Auto, (flexed-padding).max(0.), Some(main));
+            }
+
+            let free = free(&line.sizes);
+            let (start, between) = match flex.justify {
+                JustifyContent::FlexStart => (0., 0.),
+                JustifyContent::FlexEnd => (free, 0.),
+                JustifyContent::Center => (free/2., 0.),
+                JustifyContent::SpaceBetween if free > 0. && count > 1. => (0., free/(count-1.)),
+                JustifyContent::SpaceBetween => (0., 0.),
+                JustifyContent::SpaceAround if free > 0. => (free/count/2., free/count),
+                JustifyContent::SpaceEvenly if free > 0. => (free/(count+1.), free/(count+1.)),
+                JustifyContent::SpaceAround | JustifyContent::SpaceEvenly => (free/2., 0.),
+            };
+
+            let line_cross = match cross {
+                Some(cross) if single_line => cross,
+                _ => line.sizes.iter().zip(outside.iter()).map(|(s, o)| s.1+o.1).fold(0., f32::max)
+            };
+            let mut pos = start;
+            for (((child, computed, _), size), outside) in line.items.iter().zip(line.sizes.iter_mut()).zip(outside.iter()) {
+                let declared_cross = if row { computed.height } else { computed.width };
+                if flex.align == AlignItems::Stretch && declared_cross == Dimension::Auto {
+                    let padding = split(computed.padding.sum()).1;
+                    size.1 = computed.frame_length(!row, (line_cross-outside.1-padding).max(0.), cross);
+                }
+                let outer_main = size.0+outside.0;
+                let main_pos = if reverse { main-pos-outer_main } else { pos };
+                let cross_pos = line_start+match flex.align {
+                    AlignItems::FlexStart | AlignItems::Stretch => 0.,
+                    AlignItems::FlexEnd => line_cross-size.1-outside.1,
+                    AlignItems::Center => (line_cross-size.1-outside.1)/2.,
+                };
+                let target = origin+join(main_pos, cross_pos)+computed.outside().top_left();
+                let current = self.frame(*child).rect.pos;
+                self.shift(*child, target-current);
+                self.frame_mut(*child).rect.size = join(size.0, size.1);
+                pos += outer_main+main_gap+between;
+            }
+            line_start += line_cross;
+        }
+        line_start
+    }
+
+    /// Places the children of a grid container into their areas, `origin` is the corner of its content box.
+    /// Returns the size of the grid
+    fn arrange_grid(&mut self, id: UiId, origin: Vec2f, available: (Option<f32>, Option<f32>)) -> Vec2f {
+        let sys = self.sys;
+        let elem = sys.things.get(id).unwrap();
+        let grid = elem.grid_container();
+
+        // Items with both lines known are placed first, the rest fill free cells row by row
+        let mut occupied = HashSet::new();
+        let mut columns = grid.columns.len().max(grid.areas.iter().map(|e| e.len()).max().unwrap_or(0)).max(1);
+        let mut placements = vec![];
+        for child in sys.in_flow_children(id) {
+            let [column_start, column_end, row_start, row_end] = sys.things.get(child).unwrap().grid_item();
+            let column = grid.resolve_lines(&column_start, &column_end, true);
+            let row = grid.resolve_lines(&row_start, &row_end, false);
+            columns = columns.max(column.0.unwrap_or(0)+column.1);
+            placements.push((child, column, row));
+        }
+        let occupy = |x: usize, y: usize, w: usize, h: usize, occupied: &mut HashSet<(usize, usize)>| {
+            for cell in (x..x+w).flat_map(|x| (y..y+h).map(move |y| (x, y))) {
+                occupied.insert(cell);
+            }
+        };
+        let is_free = |x: usize, y: usize, w: usize, h: usize, occupied: &HashSet<(usize, usize)>| {
+            (x..x+w).all(|x| (y..y+h).all(|y| !occupied.contains(&(x, y))))
+        };
+        let mut areas = vec![];
+        for (_, (x, w), (y, h)) in placements.iter() {
+            if let (Some(x), Some(y)) = (x, y) {
+                occupy(*x, *y, *w, *h, &mut occupied);
+            }
+        }
+        let mut cursor = (0, 0);
+        for (child, (x, w), (y, h)) in placements {
+            let (x, y) = match (x, y) {
+                (Some(x), Some(y)) => (x, y),
+                (Some(x), None) => (x, (0..).find(|y| is_free(x, *y, w, h, &occupied)).unwrap()),
+                (None, Some(y)) => ((0..).find(|x| is_free(*x, y, w, h, &occupied)).unwrap(), y),
+                (None, None) => {
+                    let w = w.min(columns);
+                    let mut position = cursor;
+                    while position.0+w > columns || !is_free(position.0, position.1, w, h, &occupied) {
+                        position = if position.0+1 >= columns { (0, position.1+1) } else { (position.0+1, position.1) };
+                    }
+                    cursor = (position.0+w, position.1);
+                    position
+                }
+            };
+            occupy(x, y, w, h, &mut occupied);
+            columns = columns.max(x+w);
+            areas.push((child, (x, w), (y, h)));
+        }
+        let rows = areas.iter().map(|e| (e.2).0+(e.2).1).max().unwrap_or(0).max(grid.rows.len()).max(grid.areas.len());
+
+        let outer: Vec<Vec2f> = areas.iter().map(|e| self.hypothetical[&e.0]+sys.things.get(e.0).unwrap().computed_style().outside().sum()).collect();
+        let widths = size_tracks(&grid.columns, columns, grid.column_gap, available.0,
+                                 &areas.iter().zip(outer.iter()).map(|(e, o)| ((e.1).0, (e.1).1, o.x)).collect::<Vec<_>>());
+        let heights = size_tracks(&grid.rows, rows, grid.row_gap, available.1,
+                                  &areas.iter().zip(outer.iter()).map(|(e, o)| ((e.2).0, (e.2).1, o.y)).collect::<Vec<_>>());
+        // Where each track starts, and the length of a range of tracks with the gaps in between
+        let start = |sizes: &[f32], gap: f32, i: usize| sizes[..i].iter().sum::<f32>()+gap*i as f32;
+        let length = |sizes: &[f32], gap: f32, i: usize, n: usize| sizes[i..i+n].iter().sum::<f32>()+gap*(n-1) as f32;
+
+        let align = extract!(elem.get_style(StyleRuleTag::AlignItems), StyleRule::AlignItems(a) => a);
+        for (child, (x, w), (y, h)) in areas {
+            let computed = sys.things.get(child).unwrap().computed_style();
+            let outside = computed.outside();
+            let cell = Vec2f::new(length(&widths, grid.column_gap, x, w), length(&heights, grid.row_gap, y, h));
+            let hypothetical = self.hypothetical[&child];
+            // Items fill their area unless they have a size, percentages are of the area
+            let width = match computed.width {
+                Dimension::Auto => computed.frame_length(true, (cell.x-outside.sum().x-computed.padding.sum().x).max(0.), Some(cell.x)),
+                _ => computed.frame_length(true, hypothetical.x-computed.padding.sum().x, Some(cell.x))
+            };
+            let height = match (computed.height, align) {
+                (Dimension::Auto, AlignItems::Stretch) => computed.frame_length(false, (cell.y-outside.sum().y-computed.padding.sum().y).max(0.), Some(cell.y)),
+                _ => computed.frame_length(false, hypothetical.y-computed.padding.sum().y, Some(cell.y))
+            };
+            let free = cell.y-height-outside.sum().y;
+            let offset_y = match align {
+                AlignItems::FlexStart | AlignItems::Stretch => 0.,
+                AlignItems::FlexEnd => free,
+                AlignItems::Center => free/2.,
+            };
+            let target = origin+Vec2f::new(start(&widths, grid.column_gap, x), start(&heights, grid.row_gap, y)+offset_y)+outside.top_left();
+            let current = self.frame(child).rect.pos;
+            self.shift(child, target-current);
+            self.frame_mut(child).rect.size = Vec2f::new(width, height);
+        }
+        let total = |sizes: &[f32], gap: f32| if sizes.is_empty() { 0. } else { length(sizes, gap, 0, sizes.len()) };
+        Vec2f::new(total(&widths, grid.column_gap), total(&heights, grid.row_gap))
+    }
+
+    /// `container` is the content size of the parent on the axes where it doesn't depend on children
+    /// Lays out the element and its descendants at their own size starting at `offset`, returns the size
+    /// taken together with margins. A subtree that didn't change since it was laid out in the same container is
+    /// taken from the cache and moved to `offset`
+    fn diverge(&mut self, id: UiId, offset: Vec2f, container: (Option<f32>, Option<f32>)) -> Vec2f {
+        let start = self.result.len();
+        let cached = self.sys.layout_cache.borrow().get(&id).filter(|e| e.container == container).map(|e| (e.offset, e.outer));
+        let outer = match cached {
+            Some((cached_offset, outer)) => {
+                self.restore(id, offset-cached_offset);
+                outer
+            },
+            None => {
+                self.nested.push(vec![]);
+                let outer = self.layout_subtree(id, offset, container);
+                let nested = self.nested.pop().unwrap();
+                // Frames between the ones of the nested elements were pushed by the element itself
+                let mut frames = vec![];
+                let mut next = start;
+                for (_, _, range) in nested.iter() {
+                    frames.extend_from_slice(&self.result[next..range.start]);
+                    next = range.end;
+                }
+                frames.extend_from_slice(&self.result[next..]);
+                let hypothetical = nested.iter().filter_map(|e| self.hypothetical.get(&e.0).map(|h| (e.0, *h))).collect();
+                let nested = nested.into_iter().map(|(child, offset, _)| (child, offset, self.frame(child).rect)).collect();
+                let margins = self.margins.get(&id).copied();
+                self.sys.layout_cache.borrow_mut().insert(id, CachedLayout { offset, container, outer, frames, nested, hypothetical, margins });
+                outer
+            }
+        };
+        let end = self.result.len();
+        if let Some(parent) = self.nested.last_mut() {
+            parent.push((id, offset, start..end));
+        }
+        outer
+    }
+
+    /// Pushes the frames of an element and of everything laid out inside of it from the cache, moved by `delta`
+    fn restore(&mut self, id: UiId, delta: Vec2f) {
+        let sys = self.sys;
+        let cache = sys.layout_cache.borrow();
+        let cached = &cache[&id];
+        for (child, offset, rect) in cached.nested.iter() {
+            self.restore(*child, *offset+delta-cache[child].offset);
+            let current = self.frame(*child).rect.pos;
+            self.shift(*child, rect.pos+delta-current);
+            self.frame_mut(*child).rect.size = rect.size;
+        }
+        for frame in cached.frames.iter() {
+            let mut frame = frame.clone();
+            frame.translate(delta);
+            self.push(frame);
+        }
+        self.hypothetical.extend(cached.hypothetical.iter().copied());
+        if let Some(margins) = cached.margins {
+            self.margins.insert(id, margins);
+        }
+    }
+
+    fn layout_subtree(&mut self, id: UiId, offset: Vec2f, container: (Option<f32>, Option<f32>)) -> Vec2f {
         let elem = self.sys.things.get(id).unwrap();
+        if let Ui::Text { .. } = elem.elem {
+            // Text that isn't part of a line, like a flex item, gets lines of its own
+            return self.layout_inline(&[id], offset, container.0, container);
+        }
         let computed = elem.computed_style();
-        let global_offset = offset+computed.margin;
+        let inner_container = computed.definite_content(container, self.sys.is_stretched(id));
+        // Lines are broken at the width of the content box, which can't grow past the container
+        let line_limit = inner_container.0.or(computed.definite_content(container, true).0);
+        let global_offset = offset+computed.outside().top_left();
+        let content_offset = global_offset+computed.padding.top_left();
         let mut size = Vec2f::new(0., 0.);
-        let mut predecessor = DisplayType::Block;
-        let mut min_y = 0.;
-        let mut acc_x = 0_f32;
-        for i in elem.children.iter() {
-            match self.sys.things.get(*i).unwrap().display() {
-                DisplayType::Block => {
-                    let inner_size = self.diverge(*i, global_offset+computed.padding+Vec2f::new(0., size.y), depth + 1);
-                    size.x = size.x.max(inner_size.x);
-                    size.y += inner_size.y;
-                    min_y = size.y;
-                    acc_x = 0.;
-                    predecessor = DisplayType::Block;
+        let flex = if elem.display() == DisplayType::Flex { Some(elem.flex_container()) } else { None };
+        let grid = elem.display() == DisplayType::Grid;
+        let sys = self.sys;
+        if flex.is_some() || grid {
+            for i in elem.children.iter().filter(|e| sys.things.get(**e).unwrap().is_out_of_flow()) {
+                self.diverge(*i, content_offset, inner_container);
+            }
+        }
+        if let Some(flex) = &flex {
+            // Items are laid out at their own size first and moved into place by `arrange_flex`
+            let row = flex.is_row();
+            let container_main = if row { inner_container.0 } else { inner_container.1 };
+            let (mut main, mut cross) = (0_f32, 0_f32);
+            for (n, i) in sys.in_flow_children(id).enumerate() {
+                let outer = self.diverge(i, content_offset, inner_container);
+                self.hypothetical.insert(i, self.result.last().unwrap().rect.size);
+                let outside = sys.things.get(i).unwrap().computed_style().outside().sum();
+                let base = self.flex_base(i, row, container_main)+if row { outside.x } else { outside.y };
+                main += base+if n > 0 { flex.gaps().0 } else { 0. };
+                cross = cross.max(if row { outer.y } else { outer.x });
+            }
+            size = if row { Vec2f::new(main, cross) } else { Vec2f::new(cross, main) };
+        }
+        if grid {
+            // Same as with flex, items get their own size first and are placed by `arrange_grid`
+            for i in sys.in_flow_children(id) {
+                self.diverge(i, content_offset, inner_container);
+                self.hypothetical.insert(i, self.result.last().unwrap().rect.size);
+            }
+            size = self.arrange_grid(id, content_offset, inner_container);
+        }
+        // Vertical margins of blocks collapse with the adjoining margins of their siblings, and with the margins
+        // of the element when no border or padding separates them. `top` is the margin above the element,
+        // `pending` the one below the last block, `at_top` is set while nothing separates the content from `top`
+        let collapses = sys.in_block_flow(id) && elem.display() == DisplayType::Block;
+        let mut top = CollapsedMargin::new(computed.margin.t);
+        let mut at_top = collapses && computed.border.t == 0. && computed.padding.t == 0.;
+        let mut pending = CollapsedMargin::default();
+        // Inline level children are collected until a block interrupts them and then broken into lines together,
+        // out of flow ones stay with them so they are placed where they would be in the lines
+        let mut inline = vec![];
+        let flow = elem.children.iter().copied().filter(|_| flex.is_none() && !grid).map(Some).chain(std::iter::once(None));
+        for i in flow {
+            let block = i.filter(|i| {
+                let child = sys.things.get(*i).unwrap();
+                !child.is_out_of_flow() && child.display() != DisplayType::Inline
+            });
+            if let (Some(i), None) = (i, block) {
+                inline.push(i);
+                continue;
+            }
+            if !inline.is_empty() {
+                let start = size.y+pending.value();
+                let lines = self.layout_inline(&inline, content_offset+Vec2f::new(0., start), line_limit, inner_container);
+                size.x = size.x.max(lines.x);
+                // Lines without height, like ones with only out of flow elements, don't separate margins
+                if lines.y > 0. {
+                    size.y = start+lines.y;
+                    pending = CollapsedMargin::default();
+                    at_top = false;
+                }
+                inline.clear();
+            }
+            if let Some(i) = block {
+                let outer = self.diverge(i, content_offset+Vec2f::new(0., size.y), inner_container);
+                size.x = size.x.max(outer.x);
+                let margin = sys.things.get(i).unwrap().computed_style().margin;
+                let (child_top, child_bottom, through) = self.margins.get(&i).copied()
+                    .unwrap_or((CollapsedMargin::new(margin.t), CollapsedMargin::new(margin.b), false));
+                // The block was placed below its own top margin, it moves below the collapsed one
+                let gap = if at_top {
+                    top = top.join(child_top);
+                    0.
+                } else {
+                    pending.join(child_top).value()
+                };
+                self.shift(i, Vec2f::new(0., gap-margin.t));
+                if through {
+                    if !at_top {
+                        pending = pending.join(child_top);
+                    }
+                } else {
+                    size.y += gap+outer.y-margin.sum().y;
+                    pending = child_bottom;
+                    at_top = false;
+                }
+            }
+        }
+        // The margin of the last block goes below the element's own, unless something separates them
+        let mut bottom = CollapsedMargin::new(computed.margin.b);
+        let bottom_open = collapses && computed.border.b == 0. && computed.padding.b == 0. && computed.height == Dimension::Auto;
+        if bottom_open {
+            bottom = bottom.join(pending);
+        } else {
+            size.y += pending.value();
+        }
+        // Padding and declared sizes
+        size = Vec2f::new(computed.frame_length(true, size.x, container.0), computed.frame_length(false, size.y, container.1));
+        if let Some(flex) = &flex {
+            let padding = computed.padding.sum();
+            if flex.is_row() {
+                let used = self.arrange_flex(id, content_offset, size.x-padding.x, inner_container.1);
+                size.y = computed.frame_length(false, used, container.1);
+            } else {
+                let used = self.arrange_flex(id, content_offset, size.y-padding.y, inner_container.0);
+                size.x = computed.frame_length(true, used, container.0);
+            }
+        }
+        // Empty elements let the margins above and below them collapse together
+        let through = at_top && bottom_open && size.y == 0.;
+        if through {
+            top = top.join(bottom);
+            bottom = top;
+        }
+        self.margins.insert(id, (top, bottom, through));
+        self.push(Frame { rect: Rect { pos: global_offset, size: size }, zindex: 0, for_id: id, runs: vec![], clip: None, scroll: None });
+        return size+computed.outside().sum();
+    }
+
+    /// Breaks inline level elements into lines starting at `origin`, returns the size taken by the lines
+    fn layout_inline(&mut self, items: &[UiId], origin: Vec2f, limit: Option<f32>, container: (Option<f32>, Option<f32>)) -> Vec2f {
+        let mut line = LineBox::new(origin, limit);
+        for i in items {
+            self.place_inline(*i, &mut line, container);
+        }
+        line.size()
+    }
+
+    fn place_inline(&mut self, id: UiId, line: &mut LineBox, container: (Option<f32>, Option<f32>)) {
+        let sys = self.sys;
+        let elem = sys.things.get(id).unwrap();
+        if elem.is_out_of_flow() {
+            // Laid out where it would be in the lines, `place_positioned` moves it later
+            self.diverge(id, line.pos(), container);
+            return
+        }
+        if let Ui::Text { text } = &elem.elem {
+            return self.place_text(id, text, line)
+        }
+        let computed = elem.computed_style();
+        let sized = computed.width != Dimension::Auto || computed.height != Dimension::Auto;
+        let inline_box = elem.display() == DisplayType::Inline && !sized && elem.children.iter().all(|e| {
+            let child = sys.things.get(*e).unwrap();
+            child.is_out_of_flow() || child.display() == DisplayType::Inline
+        });
+        if inline_box {
+            // Its content goes into the same lines, when broken across them the frame covers all the pieces
+            let (outside, padding) = (computed.outside(), computed.padding);
+            line.x += outside.l+padding.l;
+            line.open.push(None);
+            for child in elem.children.iter() {
+                self.place_inline(*child, line, container);
+            }
+            let content = line.open.pop().unwrap().unwrap_or(Rect { pos: line.pos(), size: Vec2f::new(0., 0.) });
+            line.x += padding.r+outside.r;
+            let rect = Rect { pos: content.pos-padding.top_left(), size: content.size+padding.sum() };
+            line.cover(rect);
+            self.push(Frame { rect, zindex: 0, for_id: id, runs: vec![], clip: None, scroll: None });
+            return
+        }
+        // Anything else, like inline elements with blocks inside or with a size, is placed as a whole
+        // and moves to the next line if it doesn't fit
+        let start = line.pos()+Vec2f::new(line.pending_space(), 0.);
+        let outer = self.diverge(id, start, container);
+        if !line.fits(outer.x) {
+            line.new_line();
+            self.shift(id, line.pos()-start);
+        }
+        line.put(outer.x, outer.y);
+    }
+
+    fn place_text(&mut self, id: UiId, text: &str, line: &mut LineBox) {
+        let elem = self.sys.things.get(id).unwrap();
+        let style = elem.text_style(&self.sys.fonts);
+        let font = self.sys.fonts.get(style.font);
+        let text_width = |text: &str| style.measure(font, text) as f32;
+        let line_height = style.line_height as f32;
+        // Glyphs are centered on taller lines
+        let leading = ((style.line_height-font.line_height*style.scale)/2) as f32;
+        let (white_space, word_break) = (elem.white_space(), elem.word_break());
+        let start = line.pos();
+        // Pieces on the same line are joined into one run, paired with the line they are on
+        let mut runs: Vec<(usize, TextRun)> = vec![];
+        let mut area: Option<Rectf> = None;
+        let mut put = |line: &mut LineBox, text: &str| {
+            let space = line.pending_space();
+            let rect = line.put(text_width(text), line_height);
+            let spaced = if space > 0. { format!(" {}", text) } else { text.to_string() };
+            match runs.last_mut() {
+                Some((index, run)) if *index == line.index => run.text.push_str(&spaced),
+                _ => runs.push((line.index, TextRun { pos: rect.pos-Vec2f::new(space, -leading), text: spaced }))
+            }
+            area = Some(area.map_or(rect, |e| e.union(rect)));
+        };
+        for piece in split_text(text, white_space) {
+            match piece {
+                TextPiece::Newline => {
+                    line.height = line.height.max(line_height);
+                    line.new_line();
                 },
-                DisplayType::Inline => {
-                    if predecessor == DisplayType::Block {
-                        let inner_size = self.diverge(*i, global_offset+computed.padding+Vec2f::new(0., size.y), depth + 1);
-                        size.x = size.x.max(inner_size.x);
-                        size.y += inner_size.y;
-                        acc_x += inner_size.x;
+                TextPiece::Space(_) if white_space.collapses() => line.space = text_width(" "),
+                // Kept spaces never move to the next line, they hang past the end of it instead
+                TextPiece::Space(spaces) => put(line, spaces),
+                TextPiece::Word(word) => {
+                    let wraps = white_space.wraps();
+                    let width = text_width(word);
+                    if wraps && word_break != WordBreak::BreakAll && !line.fits(width) {
+                        line.new_line();
                     }
-                    else {
-                        let inner_size = self.diverge(*i, global_offset+computed.padding+Vec2f::new(acc_x, min_y), depth + 1);
-                        acc_x += inner_size.x;
-                        size.y = size.y.max(inner_size.y);
-                        if acc_x > size.x {
-                            size.x += inner_size.x;
+                    if wraps && word_break != WordBreak::Normal && line.overflows(width) {
+                        for (n, c) in word.char_indices() {
+                            let c = &word[n..n+c.len_utf8()];
+                            if !line.fits(text_width(c)) {
+                                line.new_line();
+                            }
+                            put(line, c);
                         }
-                    };
-                    predecessor = DisplayType::Inline;
+                    } else {
+                        put(line, word);
+                    }
                 }
             }
         }
-        // Additional size factors
-        size = size + match &elem.elem {
-            Ui::Text {text} => Vec2f::new(text.len() as f32*8., 16.),
-            _ => Vec2f::new(0., 0.)
-        };
-        // Padding
-        size = size + computed.padding*2.;
-        self.result.push(Frame { rect: Rect { pos: global_offset, size: size }, zindex: depth, for_id: id });
-        return size+computed.margin*2.;
+        let rect = area.unwrap_or(Rect { pos: start, size: Vec2f::new(0., 0.) });
+        self.push(Frame { rect, zindex: 0, for_id: id, runs: runs.into_iter().map(|e| e.1).collect(), clip: None, scroll: None });
     }
 }
 
 impl UiSystem {
-    pub fn calculate_layout(&self) -> Vec<Frame> {
-        LayoutCalculator::new(self).calculate_layout()
+    /// Scrolls the frontmost scroll container under `point` that can still move by `delta`,
+    /// returns whether anything was scrolled so the layout has to be calculated again
+    pub fn scroll_at(&mut self, frames: &[Frame], point: Vec2f, delta: Vec2f) -> bool {
+        for frame in frames.iter().rev() {
+            let area = match frame.scroll { Some(area) => area, None => continue };
+            if !frame.rect.contains(point) || !frame.clip.is_none_or(|e| e.contains(point)) {
+                continue
+            }
+            let max = area.content-frame.rect.size;
+            let moved = |i: usize, offset: f32, delta: f32, max: f32| if area.scrolls[i] { (offset+delta).min(max).max(0.) } else { offset };
+            let offset = Vec2f::new(moved(0, area.offset.x, delta.x, max.x), moved(1, area.offset.y, delta.y, max.y));
+            if offset.x != area.offset.x || offset.y != area.offset.y {
+                self.scroll.insert(frame.for_id, offset);
+                return true
+            }
+        }
+        false
     }
 
-    pub fn propagate_styles_rec(&mut self, id: UiId, mut propagate: HashMap<StyleRuleTag, Style>) {
-        for (key, style) in propagate.iter() {
-            if matches!(key, StyleRuleTag::BackgroundColor) {
-                unreachable!();
-            }
-            if !self.things.get(id).unwrap().styles.contains_key(&key) {
-                self.things.get_mut(id).unwrap().styles.insert(*key, *style);
+    fn in_flow_children(&self, id: UiId) -> impl Iterator<Item = UiId> + '_ {
+        self.things.get(id).unwrap().children.iter().copied().filter(move |e| !self.things.get(*e).unwrap().is_out_of_flow())
+    }
+
+    /// Back to front order of the element and its descendants, `id` has to be the root of a stacking context.
+    /// Descendants with a negative z-index go behind the flow, positioned ones and those with a positive z-index in front of it
+    fn paint_order(&self, id: UiId, out: &mut Vec<UiId>) {
+        fn walk(sys: &UiSystem, id: UiId, flow: &mut Vec<UiId>, layers: &mut Vec<(i32, Vec<UiId>)>) {
+            for child in sys.things.get(id).unwrap().children.iter().copied() {
+                let elem = sys.things.get(child).unwrap();
+                if elem.creates_stacking_context() {
+                    let mut group = vec![];
+                    sys.paint_order(child, &mut group);
+                    let z_index = extract!(elem.get_style(StyleRuleTag::ZIndex), StyleRule::ZIndex(z) => z);
+                    layers.push((z_index.unwrap_or(0), group));
+                } else if elem.position() != Position::Static {
+                    // Painted like a stacking context, but positioned descendants still belong to the outer one
+                    let index = layers.len();
+                    layers.push((0, vec![]));
+                    let mut group = vec![child];
+                    walk(sys, child, &mut group, layers);
+                    layers[index].1 = group;
+                } else {
+                    flow.push(child);
+                    walk(sys, child, flow, layers);
+                }
             }
         }
-        let this = self as *mut Self;
-        let styles = &mut self.things.get(id).unwrap().styles.iter().filter(|(_, e)| e.propagating).map(|(k, e)| (*k, *e));
-        propagate.extend(styles);
-        let children = self.things.get(id).unwrap().children.iter().copied();
-        for child in children {
-            unsafe {
-                (*this).propagate_styles_rec(child, propagate.clone());
+
+        let mut flow = vec![];
+        let mut layers = vec![];
+        walk(self, id, &mut flow, &mut layers);
+        // Stable, so the tree order is kept within the same z-index
+        layers.sort_by_key(|e| e.0);
+        out.push(id);
+        out.extend(layers.iter().filter(|e| e.0 < 0).flat_map(|e| e.1.iter().copied()));
+        out.extend(flow);
+        out.extend(layers.iter().filter(|e| e.0 >= 0).flat_map(|e| e.1.iter().copied()));
+    }
+
+    /// Block level elements in the normal flow of a block container, their vertical margins collapse with adjoining ones
+    fn in_block_flow(&self, id: UiId) -> bool {
+        let elem = self.things.get(id).unwrap();
+        let flow = elem.parent.and_then(|p| self.things.get(p)).is_some_and(|p| matches!(p.display(), DisplayType::Block | DisplayType::Inline));
+        flow && !elem.is_out_of_flow() && elem.display() != DisplayType::Inline
+    }
+
+    /// Block level elements take the width of the parent unless they are flex or grid items or out of the flow
+    fn is_stretched(&self, id: UiId) -> bool {
+        let elem = self.things.get(id).unwrap();
+        let item = elem.parent.and_then(|p| self.things.get(p)).is_some_and(|p| matches!(p.display(), DisplayType::Flex | DisplayType::Grid));
+        !item && !elem.is_out_of_flow() && elem.stretch() != StretchType::False && matches!(elem.display(), DisplayType::Block | DisplayType::Flex | DisplayType::Grid)
+    }
+
+    /// Marks the element and its ancestors dirty, so the next layout lays them out again
+    pub fn mark_dirty(&self, id: UiId) {
+        let mut cache = self.layout_cache.borrow_mut();
+        let mut current = Some(id);
+        while let Some(id) = current {
+            cache.remove(&id);
+            current = self.things.get(id).and_then(|e| e.parent);
+        }
+    }
+
+    /// Declares a style of the element. Inherited values and the way children are laid out
+    /// can change with it, so the whole subtree is marked dirty
+    pub fn set_style(&mut self, id: UiId, tag: StyleRuleTag, style: Style) {
+        self.things.get_mut(id).unwrap().styles.insert(tag, style);
+        self.restyled(id);
+    }
+
+    /// Replaces every declared style of the element, like when other rules of the stylesheet match it
+    pub fn set_styles(&mut self, id: UiId, styles: HashMap<StyleRuleTag, Style>) {
+        self.things.get_mut(id).unwrap().styles = styles;
+        self.restyled(id);
+    }
+
+    fn restyled(&mut self, id: UiId) {
+        self.compute_styles_from(id);
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            self.layout_cache.get_mut().remove(&id);
+            stack.extend(self.things.get(id).unwrap().children.iter().copied());
+        }
+        self.mark_dirty(id);
+    }
+
+    /// Replaces the text of a text element
+    pub fn set_text(&mut self, id: UiId, text: String) {
+        if let Ui::Text { text: current } = &mut self.things.get_mut(id).unwrap().elem {
+            *current = text;
+            self.mark_dirty(id);
+        }
+    }
+
+    /// Text is measured with the fonts, so everything is laid out again
+    pub fn set_fonts(&mut self, fonts: Fonts) {
+        self.fonts = fonts;
+        self.layout_cache.get_mut().clear();
+    }
+
+    /// Frames of every element, back to front. The root is laid out in `viewport` when there is one,
+    /// otherwise it takes the size of its content
+    pub fn calculate_layout(&self, viewport: Option<Vec2f>) -> Vec<Frame> {
+        LayoutCalculator::new(self, viewport).calculate_layout()
+    }
+
+    /// Resolves declared styles of every element into computed ones, parents go before children
+    /// so inherited values are always ready
+    pub fn compute_styles(&mut self) {
+        self.compute_styles_from(self.root);
+    }
+
+    /// Same as `compute_styles`, for the element and its descendants only
+    fn compute_styles_from(&mut self, id: UiId) {
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let elem = self.things.get(id).unwrap();
+            let parent = elem.parent.and_then(|p| self.things.get(p));
+            let mut computed = HashMap::new();
+            for tag in StyleRuleTag::ALL.iter().copied() {
+                let inherited = parent.map(|p| p.get_style(tag)).unwrap_or(tag.initial());
+                let value = match elem.styles.get(&tag) {
+                    Some(Style::Value(rule)) => rule.clone(),
+                    Some(Style::Inherit) => inherited,
+                    Some(Style::Initial) => tag.initial(),
+                    Some(Style::Unset) | None => if tag.inherited() { inherited } else { tag.initial() }
+                };
+                computed.insert(tag, value);
             }
+            stack.extend(elem.children.iter().copied());
+            self.things.get_mut(id).unwrap().computed = computed;
         }
     }
-}
\ No newline at end of file
+}
+
+/// Builders and frame lookups shared by the layout tests
+#[cfg(test)]
+mod fixtures {
+    use super::*;
+
+    pub fn px(tag: StyleRuleTag, v: f32) -> (StyleRuleTag, StyleRule) {
+        (tag, StyleRule::Dimension(Dimension::Px(v)))
+    }
+
+    pub fn item(styles: Vec<(StyleRuleTag, StyleRule)>) -> Item {
+        styles.into_iter().fold(Item::build(), |item, (tag, rule)| item.style(tag, rule))
+    }
+
+    pub fn container(display: DisplayType, styles: Vec<(StyleRuleTag, StyleRule)>) -> Item {
+        item(styles).style(StyleRuleTag::Display, StyleRule::Display(display))
+    }
+
+    pub fn text(text: &str) -> Item {
+        Item::build().component(Ui::Text { text: text.to_string() })
+    }
+
+    pub fn children_of(ui: &UiSystem, id: UiId) -> Vec<UiId> {
+        ui.things.get(id).unwrap().children.clone()
+    }
+
+    pub fn frame(frames: &[Frame], id: UiId) -> &Frame {
+        frames.iter().find(|e| e.for_id == id).unwrap()
+    }
+
+    pub fn rect(frames: &[Frame], id: UiId) -> Rectf {
+        frame(frames, id).rect
+    }
+
+    pub fn bounds(frames: &[Frame], id: UiId) -> (f32, f32, f32, f32) {
+        let r = rect(frames, id);
+        (r.pos.x, r.pos.y, r.size.x, r.size.y)
+    }
+
+    /// Bounds of the root followed by the bounds of each of its children
+    pub fn layout_children(ui: &UiSystem) -> Vec<(f32, f32, f32, f32)> {
+        let frames = ui.calculate_layout(None);
+        std::iter::once(ui.root).chain(children_of(ui, ui.root)).map(|id| bounds(&frames, id)).collect()
+    }
+}
+
+#[test]
+fn test_computed_styles() {
+    use fixtures::*;
+    let red = StyleRule::Color { color: (255, 0, 0, 255) };
+    let ui = Item::build()
+        .style(StyleRuleTag::Color, red.clone())
+        .style(StyleRuleTag::BackgroundColor, red)
+        .with_children(vec![
+            Item::build(),
+            Item::build().style(StyleRuleTag::Color, Style::Initial),
+            Item::build().style(StyleRuleTag::BackgroundColor, Style::Inherit),
+            Item::build().style(StyleRuleTag::BackgroundColor, Style::Unset).style(StyleRuleTag::Color, Style::Unset),
+            Item::build().with_children(vec![text("a")]),
+        ])
+        .into_ui();
+
+    let root = ui.things.get(ui.root).unwrap();
+    let children: Vec<&UiElem> = root.children.iter().map(|e| ui.things.get(*e).unwrap()).collect();
+    assert_eq!(root.get_fg(), (255, 0, 0, 255));
+    // Color is inherited, background isn't
+    assert_eq!(children[0].get_fg(), (255, 0, 0, 255));
+    assert_eq!(children[0].get_bg(), (255, 255, 255, 0));
+    assert_eq!(children[1].get_fg(), (0, 0, 0, 255));
+    assert_eq!(children[2].get_bg(), (255, 0, 0, 255));
+    assert_eq!(children[3].get_bg(), (255, 255, 255, 0));
+    assert_eq!(children[3].get_fg(), (255, 0, 0, 255));
+    let text = ui.things.get(children[4].children[0]).unwrap();
+    assert_eq!(text.get_fg(), (255, 0, 0, 255));
+    assert_eq!(text.display(), DisplayType::Inline);
+}
+
+#[test]
+fn test_box_sides() {
+    use fixtures::*;
+    let ui = Item::build()
+        .sides(StyleRuleTag::PADDING, Sides { l: 1., t: 2., r: 3., b: 4. })
+        .sides(StyleRuleTag::BORDER_WIDTH, Sides::all(5.))
+        .style(StyleRuleTag::BorderLeftStyle, StyleRule::BorderStyle(BorderStyle::Solid))
+        .style(StyleRuleTag::BorderTopStyle, StyleRule::BorderStyle(BorderStyle::Solid))
+        .style(StyleRuleTag::BorderRightStyle, StyleRule::BorderStyle(BorderStyle::Solid))
+        .style(StyleRuleTag::BorderBottomStyle, StyleRule::BorderStyle(BorderStyle::Solid))
+        .with_children(vec![
+            Item::build()
+                .sides(StyleRuleTag::MARGIN, Sides { l: 10., t: 20., r: 30., b: 40. })
+                .sides(StyleRuleTag::BORDER_WIDTH, Sides::all(3.))
+                .padding(7., 8.)
+                .with_children(vec![text("ab")]),
+        ])
+        .into_ui();
+    let frames = ui.calculate_layout(None);
+    let frame = |id| rect(&frames, id);
+    let child = children_of(&ui, ui.root)[0];
+    let text = children_of(&ui, child)[0];
+
+    // Text is 16x16, child pads it by 7 and 8 from each side
+    assert_eq!(frame(text).size.x, 16.);
+    assert_eq!(frame(child).size.y, 16. + 16.);
+    assert_eq!((frame(text).pos.x, frame(text).pos.y), (5. + 1. + 10. + 7., 5. + 2. + 20. + 8.));
+    // Root holds child with its margins, plus its own padding on each side
+    assert_eq!(frame(ui.root).size.y, 32. + 20. + 40. + 2. + 4.);
+    assert_eq!((frame(ui.root).pos.x, frame(ui.root).pos.y), (5., 5.));
+    // Block child is stretched to the content width of the root
+    assert_eq!(frame(child).size.x, frame(ui.root).size.x - 1. - 3. - 10. - 30.);
+}
+
+#[test]
+fn test_explicit_sizes() {
+    use fixtures::*;
+    let dim = |tag, d| (tag, StyleRule::Dimension(d));
+    let ui = item(vec![dim(StyleRuleTag::Width, Dimension::Px(200.)), dim(StyleRuleTag::Height, Dimension::Px(100.))])
+        .padding(10., 5.)
+        .with_children(vec![
+            item(vec![dim(StyleRuleTag::Width, Dimension::Percent(50.)), dim(StyleRuleTag::Height, Dimension::Percent(50.))]),
+            item(vec![dim(StyleRuleTag::MaxWidth, Dimension::Px(120.)), dim(StyleRuleTag::MinHeight, Dimension::Px(30.))]).padding(2., 2.),
+            item(vec![dim(StyleRuleTag::Width, Dimension::Px(60.)), (StyleRuleTag::BoxSizing, StyleRule::BoxSizing(BoxSizing::BorderBox))])
+                .padding(5., 0.)
+                .sides(StyleRuleTag::BORDER_WIDTH, Sides::all(5.))
+                .style(StyleRuleTag::BorderLeftStyle, StyleRule::BorderStyle(BorderStyle::Solid)),
+            // Stretched block in an auto sized parent, percentages are resolved after stretching
+            item(vec![]).with_children(vec![item(vec![dim(StyleRuleTag::Width, Dimension::Percent(25.))])]),
+        ])
+        .into_ui();
+    let frames = ui.calculate_layout(None);
+    let frame = |id| rect(&frames, id);
+    let children = children_of(&ui, ui.root);
+
+    // Content box is declared, padding goes on top of it
+    assert_eq!((frame(ui.root).size.x, frame(ui.root).size.y), (220., 110.));
+    assert_eq!((frame(children[0]).size.x, frame(children[0]).size.y), (100., 50.));
+    // Stretching is clamped by max-width, the content by min-height
+    assert_eq!((frame(children[1]).size.x, frame(children[1]).size.y), (124., 34.));
+    // Border box sizing, only the left border is drawn
+    assert_eq!(frame(children[2]).size.x, 55.);
+    let nested = children_of(&ui, children[3])[0];
+    assert_eq!(frame(children[3]).size.x, 200.);
+    assert_eq!(frame(nested).size.x, 50.);
+}
+
+#[test]
+fn test_margin_collapsing() {
+    use StyleRuleTag::*;
+    use fixtures::*;
+    let margin = |t: f32, b: f32| vec![(MarginTop, StyleRule::Length(t)), (MarginBottom, StyleRule::Length(b))];
+    let block = |t, b| item([margin(t, b), vec![px(Height, 10.)]].concat());
+    // Top of every child of the root and the height of the root
+    let layout = |ui: &UiSystem| -> (Vec<f32>, f32) {
+        let frames = ui.calculate_layout(None);
+        (children_of(ui, ui.root).into_iter().map(|e| rect(&frames, e).pos.y).collect(), rect(&frames, ui.root).size.y)
+    };
+
+    // Adjacent siblings take the larger margin, margins of the root stay inside of it
+    let ui = item(vec![]).with_children(vec![block(4., 4.), block(4., 4.), block(8., 2.)]).into_ui();
+    assert_eq!(layout(&ui), (vec![4., 18., 36.], 48.));
+    // Negative margins are added to the largest positive one
+    let ui = item(vec![]).with_children(vec![block(0., 10.), block(-4., 0.), block(-2., 0.)]).into_ui();
+    assert_eq!(layout(&ui), (vec![0., 16., 24.], 34.));
+    let ui = item(vec![]).with_children(vec![block(0., -4.), block(-6., 0.)]).into_ui();
+    assert_eq!(layout(&ui), (vec![0., 4.], 14.));
+
+    // The first and the last child share their margins with the parent
+    let ui = item(vec![]).with_children(vec![
+        block(0., 0.),
+        item(margin(5., 2.)).with_children(vec![block(10., 0.), block(0., 6.)]),
+        block(0., 0.),
+    ]).into_ui();
+    let (tops, height) = layout(&ui);
+    assert_eq!((tops, height), (vec![0., 20., 46.], 56.));
+    let frames = ui.calculate_layout(None);
+    let wrapper = children_of(&ui, ui.root)[1];
+    let inner = children_of(&ui, wrapper);
+    assert_eq!((rect(&frames, wrapper).size.y, rect(&frames, inner[0]).pos.y, rect(&frames, inner[1]).pos.y), (20., 20., 30.));
+
+    // Padding, borders and lines separate the margins
+    let ui = item(vec![]).with_children(vec![
+        item(margin(5., 0.)).padding(0., 1.).with_children(vec![block(10., 0.)]),
+        item(margin(5., 0.)).with_children(vec![text("a"), block(10., 0.)]),
+    ]).into_ui();
+    let frames = ui.calculate_layout(None);
+    let children = children_of(&ui, ui.root);
+    let inner = |n: usize| *children_of(&ui, children[n]).last().unwrap();
+    assert_eq!((rect(&frames, children[0]).pos.y, rect(&frames, inner(0)).pos.y), (5., 16.));
+    assert_eq!((rect(&frames, children[1]).pos.y, rect(&frames, inner(1)).pos.y), (32., 58.));
+
+    // Margins of empty blocks collapse through them
+    let ui = item(vec![]).with_children(vec![block(0., 10.), item(margin(6., 12.)), block(4., 0.)]).into_ui();
+    assert_eq!(layout(&ui).0[2], 22.);
+    let ui = item(vec![]).with_children(vec![item(margin(0., 0.)).with_children(vec![item(margin(3., 8.))]), block(0., 0.)]).into_ui();
+    assert_eq!(layout(&ui), (vec![8., 8.], 18.));
+
+    // Flex containers keep the margins of their items inside, but collapse with their siblings
+    let ui = item(vec![]).with_children(vec![
+        block(0., 6.),
+        container(DisplayType::Flex, margin(4., 0.)).with_children(vec![block(10., 0.)]),
+    ]).into_ui();
+    let frames = ui.calculate_layout(None);
+    let flex = children_of(&ui, ui.root)[1];
+    let flex_item = children_of(&ui, flex)[0];
+    assert_eq!((rect(&frames, flex).pos.y, rect(&frames, flex_item).pos.y, rect(&frames, flex).size.y), (16., 26., 20.));
+}
+
+#[test]
+fn test_flex_layout() {
+    use StyleRuleTag::*;
+    use fixtures::*;
+    let flex = |styles| container(DisplayType::Flex, styles);
+
+    // Free space is shared by grow factors, items are stretched to the line
+    let ui = flex(vec![px(Width, 300.), (ColumnGap, StyleRule::Length(10.))]).with_children(vec![
+        item(vec![px(Width, 50.), px(Height, 30.)]),
+        item(vec![(FlexGrow, StyleRule::Number(1.))]),
+        item(vec![px(Width, 20.), (FlexGrow, StyleRule::Number(2.))]),
+    ]).into_ui();
+    assert_eq!(layout_children(&ui), vec![(0., 0., 300., 30.), (0., 0., 50., 30.), (60., 0., 70., 30.), (140., 0., 160., 30.)]);
+
+    // Column with a fixed height, centered on both axes
+    let ui = flex(vec![px(Width, 100.), px(Height, 100.),
+                       (FlexDirection, StyleRule::FlexDirection(crate::ui::FlexDirection::Column)),
+                       (JustifyContent, StyleRule::JustifyContent(crate::ui::JustifyContent::Center)),
+                       (AlignItems, StyleRule::AlignItems(crate::ui::AlignItems::Center))]).with_children(vec![
+        item(vec![px(Width, 40.), px(Height, 20.)]),
+        item(vec![px(Width, 20.), px(Height, 20.)]).sides(StyleRuleTag::MARGIN, Sides { l: 0., t: 5., r: 0., b: 0. }),
+    ]).into_ui();
+    assert_eq!(layout_children(&ui)[1..], [(30., 27.5, 40., 20.), (40., 52.5, 20., 20.)]);
+
+    // Wrapping into lines, the container grows to hold them
+    let ui = flex(vec![px(Width, 100.), (FlexWrap, StyleRule::FlexWrap(crate::ui::FlexWrap::Wrap)), (RowGap, StyleRule::Length(5.))]).with_children(vec![
+        item(vec![px(Width, 40.), px(Height, 10.)]),
+        item(vec![px(Width, 40.), px(Height, 20.)]),
+        item(vec![px(Width, 40.), px(Height, 10.)]),
+    ]).into_ui();
+    assert_eq!(layout_children(&ui), vec![(0., 0., 100., 35.), (0., 0., 40., 10.), (40., 0., 40., 20.), (0., 25., 40., 10.)]);
+
+    // Reversed row, overflowing items shrink by their size
+    let ui = flex(vec![px(Width, 100.), (FlexDirection, StyleRule::FlexDirection(crate::ui::FlexDirection::RowReverse))]).with_children(vec![
+        item(vec![px(Width, 80.), px(Height, 10.)]),
+        item(vec![px(Width, 40.), px(Height, 10.), (FlexShrink, StyleRule::Number(2.))]),
+    ]).into_ui();
+    assert_eq!(layout_children(&ui)[1..], [(30., 0., 70., 10.), (0., 0., 30., 10.)]);
+
+    // A stretched flex container is arranged again once its width is known
+    let ui = item(vec![]).with_children(vec![
+        item(vec![px(Width, 200.), px(Height, 10.)]),
+        flex(vec![(JustifyContent, StyleRule::JustifyContent(crate::ui::JustifyContent::SpaceBetween))]).with_children(vec![
+            item(vec![px(Width, 30.), px(Height, 10.)]),
+            item(vec![px(Width, 30.), px(Height, 10.)]),
+        ]),
+    ]).into_ui();
+    let frames = ui.calculate_layout(None);
+    let row = children_of(&ui, ui.root)[1];
+    let last = children_of(&ui, row)[1];
+    assert_eq!(rect(&frames, row).size.x, 200.);
+    assert_eq!(rect(&frames, last).pos.x, 170.);
+}
+
+#[test]
+fn test_grid_layout() {
+    use StyleRuleTag::*;
+    use fixtures::*;
+    let line = |tag, l| (tag, StyleRule::GridLine(l));
+    let grid = |styles| container(DisplayType::Grid, styles);
+    let area = |name: &str| vec![line(GridRowStart, GridLine::Area(name.to_string())), line(GridColumnStart, GridLine::Area(name.to_string())),
+                                 line(GridRowEnd, GridLine::Area(name.to_string())), line(GridColumnEnd, GridLine::Area(name.to_string()))];
+
+    // Named area, explicit lines and auto placement that skips taken cells
+    let ui = grid(vec![px(Width, 300.),
+                       (GridTemplateColumns, StyleRule::Tracks(vec![TrackSize::Px(100.), TrackSize::Fr(1.), TrackSize::Fr(2.)])),
+                       (GridTemplateAreas, StyleRule::Areas(vec![vec!["head".to_string(); 3]])),
+                       (ColumnGap, StyleRule::Length(10.)), (RowGap, StyleRule::Length(5.))]).with_children(vec![
+        item(area("head").into_iter().chain(vec![px(Height, 20.)]).collect()),
+        item(vec![line(GridColumnStart, GridLine::Line(1)), line(GridRowStart, GridLine::Line(2)), px(Height, 40.)]),
+        item(vec![]),
+        item(vec![line(GridColumnStart, GridLine::Span(2)), px(Height, 10.)]),
+    ]).into_ui();
+    assert_eq!(layout_children(&ui), vec![(0., 0., 300., 80.), (0., 0., 300., 20.), (0., 25., 100., 40.), (110., 25., 60., 40.), (0., 70., 170., 10.)]);
+
+    // Without a width fractions follow the content, negative lines count from the end
+    let ui = grid(vec![(GridTemplateColumns, StyleRule::Tracks(vec![TrackSize::Auto, TrackSize::Fr(1.), TrackSize::Fr(1.)]))]).with_children(vec![
+        item(vec![px(Width, 30.), px(Height, 10.)]),
+        item(vec![px(Width, 20.), px(Height, 10.)]),
+        item(vec![px(Width, 50.), px(Height, 10.)]),
+        item(vec![line(GridColumnStart, GridLine::Line(1)), line(GridColumnEnd, GridLine::Line(-1)), px(Height, 5.)]),
+    ]).into_ui();
+    assert_eq!(layout_children(&ui), vec![(0., 0., 130., 15.), (0., 0., 30., 10.), (30., 0., 20., 10.), (80., 0., 50., 10.), (0., 10., 130., 5.)]);
+
+    // Huge lines and spans are clamped to the last track instead of growing the grid to match
+    let ui = grid(vec![]).with_children(vec![
+        item(vec![line(GridColumnStart, GridLine::Line(i32::MAX)), line(GridRowStart, GridLine::Span(u32::MAX))]),
+    ]).into_ui();
+    let container = ui.things.get(ui.root).unwrap().grid_container();
+    assert_eq!(container.resolve_lines(&GridLine::Line(i32::MAX), &GridLine::Auto, true), (Some(999), 1));
+    assert_eq!(container.resolve_lines(&GridLine::Auto, &GridLine::Span(u32::MAX), false), (None, 1000));
+    assert_eq!(container.resolve_lines(&GridLine::Line(990), &GridLine::Span(20), false), (Some(989), 11));
+    assert_eq!(ui.calculate_layout(None).len(), 2);
+}
+
+#[test]
+fn test_positioning() {
+    use StyleRuleTag::*;
+    use fixtures::*;
+    let position = |p| (Position, StyleRule::Position(p));
+
+    // Relative boxes keep their place in the flow, absolute ones take none
+    let ui = item(vec![px(Width, 200.)]).with_children(vec![
+        item(vec![px(Height, 20.)]),
+        item(vec![position(crate::ui::Position::Relative), px(Left, 5.), px(Top, 3.), px(Height, 10.)]),
+        item(vec![position(crate::ui::Position::Absolute), px(Top, 10.), px(Right, 10.), px(Width, 30.), px(Height, 5.)]),
+        item(vec![px(Height, 10.)]),
+    ]).into_ui();
+    let frames = ui.calculate_layout(None);
+    let children = children_of(&ui, ui.root);
+    assert_eq!(bounds(&frames, ui.root), (0., 0., 200., 40.));
+    assert_eq!(children.iter().map(|id| bounds(&frames, *id)).collect::<Vec<_>>(),
+               vec![(0., 0., 200., 20.), (5., 23., 200., 10.), (160., 10., 30., 5.), (0., 30., 200., 10.)]);
+
+    // The nearest positioned ancestor is the containing block, both offsets set make an auto size fill it
+    let ui = item(vec![px(Width, 200.)]).with_children(vec![
+        item(vec![px(Height, 20.)]),
+        item(vec![position(crate::ui::Position::Relative), px(Height, 50.)]).sides(StyleRuleTag::PADDING, Sides::all(10.)).with_children(vec![
+            item(vec![position(crate::ui::Position::Absolute), px(Left, 0.), px(Right, 20.), px(Top, 5.), px(Bottom, 5.)]),
+        ]),
+    ]).into_ui();
+    let frames = ui.calculate_layout(None);
+    let parent = children_of(&ui, ui.root)[1];
+    let child = children_of(&ui, parent)[0];
+    assert_eq!(bounds(&frames, parent), (0., 20., 200., 70.));
+    assert_eq!(bounds(&frames, child), (0., 25., 180., 60.));
+
+    // Negative z-index goes behind the flow, positioned boxes in front of it ordered by z-index,
+    // a stacking context keeps its descendants together
+    let z = |v| (ZIndex, StyleRule::ZIndex(Some(v)));
+    let ui = item(vec![]).with_children(vec![
+        item(vec![position(crate::ui::Position::Relative), z(1)]),
+        item(vec![]),
+        item(vec![position(crate::ui::Position::Relative), z(-1)]).with_children(vec![
+            item(vec![position(crate::ui::Position::Relative), z(100)]),
+        ]),
+        item(vec![position(crate::ui::Position::Absolute)]),
+    ]).into_ui();
+    let frames = ui.calculate_layout(None);
+    let children = children_of(&ui, ui.root);
+    let nested = children_of(&ui, children[2])[0];
+    assert_eq!(frames.iter().map(|e| e.for_id).collect::<Vec<_>>(),
+               vec![ui.root, children[2], nested, children[1], children[3], children[0]]);
+
+    // In a viewport the root is stretched to its width and fixed boxes are placed against it
+    let ui = item(vec![]).padding(10., 0.).with_children(vec![
+        item(vec![px(Height, 20.)]),
+        item(vec![position(crate::ui::Position::Fixed), px(Right, 0.), px(Bottom, 0.), px(Width, 50.), px(Height, 10.)]),
+    ]).into_ui();
+    let frames = ui.calculate_layout(Some(Vec2f::new(300., 200.)));
+    let children = children_of(&ui, ui.root);
+    assert_eq!(bounds(&frames, ui.root), (0., 0., 300., 20.));
+    assert_eq!(bounds(&frames, children[0]), (10., 0., 280., 20.));
+    assert_eq!(bounds(&frames, children[1]), (250., 190., 50., 10.));
+}
+
+#[test]
+fn test_overflow_scrolling() {
+    use StyleRuleTag::*;
+    use fixtures::*;
+    let row = || item(vec![px(Height, 20.)]);
+    let mut ui = item(vec![px(Width, 100.)]).with_children(vec![
+        row(),
+        item(vec![px(Height, 50.), (OverflowY, StyleRule::Overflow(Overflow::Auto))]).with_children(vec![
+            row(), row(), row().with_children(vec![item(vec![px(Width, 150.), px(Height, 5.)])]), row(),
+            item(vec![(Position, StyleRule::Position(crate::ui::Position::Absolute)), px(Width, 10.), px(Height, 10.)]),
+        ]),
+    ]).into_ui();
+    let container = children_of(&ui, ui.root)[1];
+    let rows = children_of(&ui, container);
+    let nested = children_of(&ui, rows[2])[0];
+    let frames = ui.calculate_layout(None);
+    let rect = |r: Rectf| (r.pos.x, r.pos.y, r.size.x, r.size.y);
+
+    // Content is clipped to the padding box, the wide child doesn't make the visible x axis scroll
+    let area = frame(&frames, container).scroll.unwrap();
+    assert_eq!((area.content.x, area.content.y, area.bars), (150., 80., [false, true]));
+    assert_eq!(frame(&frames, rows[0]).clip.map(rect), Some((0., 20., 100., 50.)));
+    assert_eq!(frame(&frames, nested).clip.map(rect), Some((0., 20., 100., 50.)));
+    assert!(frame(&frames, container).clip.is_none());
+    // The containing block of the absolute box is outside of the container, so it isn't clipped
+    assert!(frame(&frames, rows[4]).clip.is_none());
+
+    // Scrolling is clamped to the content, and only happens over the container
+    assert!(!ui.scroll_at(&frames, Vec2f::new(10., 10.), Vec2f::new(0., 10.)));
+    assert!(ui.scroll_at(&frames, Vec2f::new(10., 30.), Vec2f::new(0., 100.)));
+    let frames = ui.calculate_layout(None);
+    assert_eq!(frame(&frames, container).scroll.unwrap().offset.y, 30.);
+    assert_eq!(frame(&frames, rows[0]).rect.pos.y, -10.);
+    assert_eq!(frame(&frames, nested).rect.pos.y, 30.);
+    assert!(!ui.scroll_at(&frames, Vec2f::new(10., 30.), Vec2f::new(0., 10.)));
+    assert!(ui.scroll_at(&frames, Vec2f::new(10., 30.), Vec2f::new(0., -40.)));
+    assert_eq!(frame(&ui.calculate_layout(None), container).scroll.unwrap().offset.y, 0.);
+}
+
+#[test]
+fn test_incremental_layout() {
+    use StyleRuleTag::*;
+    use fixtures::*;
+    let mut ui = item(vec![px(Width, 100.)]).with_children(vec![
+        item(vec![]).with_children(vec![text("ab")]),
+        item(vec![px(Height, 10.)]).with_children(vec![text("cd")]),
+    ]).into_ui();
+    let children = children_of(&ui, ui.root);
+    let text = children_of(&ui, children[0])[0];
+    let other = children_of(&ui, children[1])[0];
+    let layout = |ui: &UiSystem| -> Vec<(f32, f32)> {
+        let frames = ui.calculate_layout(None);
+        [ui.root, children[0], children[1], other].iter().map(|id| (rect(&frames, *id).pos.y, rect(&frames, *id).size.y)).collect()
+    };
+    assert_eq!(layout(&ui), vec![(0., 26.), (0., 16.), (16., 10.), (16., 16.)]);
+
+    // Changes the cache doesn't know about only show once the element is marked dirty
+    if let Ui::Text { text } = &mut ui.things.get_mut(text).unwrap().elem {
+        *text = "ab cd ef gh ij kl".to_string();
+    }
+    assert_eq!(layout(&ui), vec![(0., 26.), (0., 16.), (16., 10.), (16., 16.)]);
+    ui.mark_dirty(text);
+    // The clean sibling is reused and moved down with its text
+    assert_eq!(layout(&ui), vec![(0., 42.), (0., 32.), (32., 10.), (32., 16.)]);
+
+    ui.set_text(text, "ab".to_string());
+    ui.set_style(children[1], Height, Style::Value(StyleRule::Dimension(Dimension::Px(30.))));
+    assert_eq!(layout(&ui), vec![(0., 46.), (0., 16.), (16., 30.), (16., 16.)]);
+    // Inherited values reach the descendants
+    ui.set_style(ui.root, FontSize, Style::Value(StyleRule::Dimension(Dimension::Px(32.))));
+    assert_eq!(layout(&ui), vec![(0., 62.), (0., 32.), (32., 30.), (32., 32.)]);
+
+    // Cached subtrees that their parents moved and resized are put back the same as laid out from scratch
+    let tree = |first: &str| item(vec![px(Width, 120.)]).with_children(vec![
+        item(vec![]).with_children(vec![fixtures::text(first)]),
+        container(DisplayType::Flex, vec![(ColumnGap, StyleRule::Length(4.))]).with_children(vec![
+            item(vec![(FlexGrow, StyleRule::Number(1.))]).with_children(vec![fixtures::text("ab cd")]),
+            item(vec![px(Width, 30.), (MarginTop, StyleRule::Length(6.))]),
+        ]),
+        container(DisplayType::Grid, vec![(GridTemplateColumns, StyleRule::Tracks(vec![TrackSize::Fr(1.), TrackSize::Fr(1.)]))]).with_children(vec![
+            item(vec![]).with_children(vec![fixtures::text("ef gh"), container(DisplayType::Inline, vec![px(Width, 20.), px(Height, 20.)])]),
+            fixtures::text("gh ij kl"),
+        ]),
+        item(vec![]).with_children(vec![
+            item(vec![px(Height, 10.), (MarginBottom, StyleRule::Length(10.))]),
+            item(vec![px(Height, 10.), (MarginTop, StyleRule::Length(4.))]),
+        ]),
+    ]);
+    let every_frame = |ui: &UiSystem| -> Vec<(UiId, (f32, f32, f32, f32), Vec<(f32, f32)>)> {
+        ui.calculate_layout(None).iter().map(|e| (e.for_id, bounds(std::slice::from_ref(e), e.for_id), e.runs.iter().map(|r| (r.pos.x, r.pos.y)).collect())).collect()
+    };
+    let mut ui = tree("ab").into_ui();
+    let first = children_of(&ui, children_of(&ui, ui.root)[0])[0];
+    every_frame(&ui);
+    ui.set_text(first, "ab cd ef gh ij kl mn".to_string());
+    assert_eq!(every_frame(&ui), every_frame(&tree("ab cd ef gh ij kl mn").into_ui()));
+}
+
+#[test]
+fn test_text_wrapping() {
+    use StyleRuleTag::*;
+    use fixtures::*;
+    let white_space = |w| (WhiteSpace, StyleRule::WhiteSpace(w));
+    let word_break = |w| (WordBreak, StyleRule::WordBreak(w));
+    // Runs of every text element in the tree order, with their positions
+    let runs = |ui: &UiSystem| -> Vec<(f32, f32, String)> {
+        let frames = ui.calculate_layout(None);
+        let mut stack = vec![ui.root];
+        let mut runs = vec![];
+        while let Some(id) = stack.pop() {
+            runs.extend(frame(&frames, id).runs.iter().map(|e| (e.pos.x, e.pos.y, e.text.clone())));
+            stack.extend(ui.things.get(id).unwrap().children.iter().rev());
+        }
+        runs
+    };
+    let run = |x, y, text: &str| (x, y, text.to_string());
+
+    // Whitespace collapses, words move to the next line when they don't fit
+    let ui = item(vec![px(Width, 80.)]).with_children(vec![text("  hello big\n   world  ")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "hello big"), run(0., 16., "world")]);
+    let frames = ui.calculate_layout(None);
+    let frame = |id| rect(&frames, id);
+    let line = children_of(&ui, ui.root)[0];
+    assert_eq!((frame(ui.root).size.x, frame(ui.root).size.y), (80., 32.));
+    assert_eq!((frame(line).size.x, frame(line).size.y), (72., 32.));
+
+    // Inline boxes share the lines, the space between elements is kept once
+    let ui = item(vec![px(Width, 64.)]).with_children(vec![
+        text("ab "),
+        item(vec![(Display, StyleRule::Display(DisplayType::Inline))]).with_children(vec![text(" cd ef")]),
+        text(" gh"),
+    ]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "ab"), run(16., 0., " cd ef"), run(0., 16., "gh")]);
+    let frames = ui.calculate_layout(None);
+    let span = children_of(&ui, ui.root)[1];
+    assert_eq!(bounds(&frames, span), (24., 0., 40., 16.));
+
+    // Preserved whitespace, with and without wrapping
+    let ui = item(vec![px(Width, 16.), white_space(crate::ui::WhiteSpace::Pre)]).with_children(vec![text("a  b\nc")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "a  b"), run(0., 16., "c")]);
+    let ui = item(vec![px(Width, 24.), white_space(crate::ui::WhiteSpace::PreWrap)]).with_children(vec![text("ab  cd")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "ab  "), run(0., 16., "cd")]);
+    let ui = item(vec![px(Width, 24.), white_space(crate::ui::WhiteSpace::NoWrap)]).with_children(vec![text("ab  cd")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "ab cd")]);
+
+    // Breaking inside words
+    let ui = item(vec![px(Width, 32.), word_break(crate::ui::WordBreak::BreakAll)]).with_children(vec![text("ab abcdefgh")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "ab a"), run(0., 16., "bcde"), run(0., 32., "fgh")]);
+    let ui = item(vec![px(Width, 32.), word_break(crate::ui::WordBreak::BreakWord)]).with_children(vec![text("ab abcdefgh")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "ab"), run(0., 16., "abcd"), run(0., 32., "efgh")]);
+    let ui = item(vec![px(Width, 32.)]).with_children(vec![text("ab abcdefgh")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "ab"), run(0., 16., "abcdefgh")]);
+    // Measured by glyph, wide characters take two columns
+    let ui = item(vec![px(Width, 40.), word_break(crate::ui::WordBreak::BreakAll)]).with_children(vec![text("日本語 ok")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "日本"), run(0., 16., "語 ok")]);
+    // Font properties are inherited, glyphs are scaled by whole numbers and centered on taller lines
+    let ui = item(vec![px(Width, 72.), px(FontSize, 32.)]).with_children(vec![text("ab cd")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 0., "ab"), run(0., 32., "cd")]);
+    let ui = item(vec![px(Width, 40.), (LetterSpacing, StyleRule::Length(2.)), (LineHeight, StyleRule::LineHeight(crate::ui::LineHeight::Px(24.)))])
+        .with_children(vec![text("ab cd")]).into_ui();
+    assert_eq!(runs(&ui), vec![run(0., 4., "ab"), run(0., 28., "cd")]);
+
+    // Inline elements with a size are placed whole
+    let inline = || item(vec![(Display, StyleRule::Display(DisplayType::Inline)), px(Width, 60.), px(Height, 20.)]);
+    let ui = item(vec![px(Width, 100.)]).with_children(vec![inline(), text("x"), inline()]).into_ui();
+    let frames = ui.calculate_layout(None);
+    let children = children_of(&ui, ui.root);
+    let pos = |id| { let r = rect(&frames, id); (r.pos.x, r.pos.y) };
+    assert_eq!((pos(children[0]), pos(children[1]), pos(children[2])), ((0., 0.), (60., 0.), (0., 20.)));
+}
@@ -1,13 +1,12 @@
+//! Builds the ui from an XML document and a stylesheet, and keeps the two in sync when either file
+//! is reloaded: restyled elements are updated in place, anything else builds the ui again.
 
-/// THIS DOCUMENT IS A STUB!!
-
-use std::{collections::HashMap, fs::File};
-use std::io::BufReader;
+use std::collections::{BTreeSet, HashMap};
 use crate::{atoms::Id, css_parser::Css, dom_repr::DomComponent};
-use crate::dom_repr::DomSystem;
-use xml::reader::{EventReader, XmlEvent};
-use crate::{css_matcher::match_selector_against_dom, ui::*};
-use crate::css_gen::generate_from;
+use crate::dom_repr::{DomSystem, MutationRecord};
+use crate::ui::*;
+use crate::css_gen::{generate_from, generate_font_faces};
+use crate::font::{Font, Fonts};
 
 
 
@@ -21,7 +20,17 @@ fn make_error(message: String, path: &str, pathcss: &str) -> UiSystem {
                 .with_children(vec![
                     Item::build().component(Ui::Text { text: "Whoops! An error:".to_string() })
                 ]),
-            Item::build().component(Ui::Text { text: message }),
+            // Each line of the message gets its own block so the caret stays under the error,
+            // whitespace is preserved so the spaces in front of the caret don't collapse
+            Item::build()
+                .style(StyleRuleTag::WhiteSpace, StyleRule::WhiteSpace(WhiteSpace::Pre))
+                .with_children(
+                message.lines()
+                    .map(|line| Item::build().with_children(vec![
+                        Item::build().component(Ui::Text { text: line.to_string() })
+                    ]))
+                    .collect()
+            ),
             Item::build(),
             Item::build().component(Ui::Text { text: "-----------------------".to_string() }),
             Item::build(),
@@ -29,78 +38,376 @@ fn make_error(message: String, path: &str, pathcss: &str) -> UiSystem {
         ]).into_ui()
 }
 
+/// Styles declared on an element by the blocks matching it. Blocks with a more specific selector override
+/// less specific ones, equally specific ones are applied in stylesheet order
+fn declared_styles(system: &DomSystem, id: Id, blocks: &[usize], css: &Css) -> HashMap<StyleRuleTag, Style> {
+    let mut styles = HashMap::new();
+    // Spans are inline, other elements and the document node are plain containers
+    if let Some("Span") = system.get_element(id).map(|e| e.tag.as_str()) {
+        styles.insert(StyleRuleTag::Display, Style::Value(StyleRule::Display(DisplayType::Inline)));
+    }
+    let mut blocks = blocks.to_vec();
+    blocks.sort_by_cached_key(|e| (css.blocks[*e].selector.matching_specificity(system, id), *e));
+    for block_id in blocks.iter() {
+        styles.extend(generate_from(&css.blocks[*block_id].rules));
+    }
+    styles
+}
+
 fn build_recursively(system: &DomSystem, root: Id, styles: &HashMap<Id, Vec<usize>>, css: &Css) -> Item {
     let components = system.firstlevel_components(root).unwrap();
-    let element = system.get_element(root).unwrap();
-    let mut item = match element.tag.as_str() {
-        "Span" => Item::build().style(StyleRuleTag::Display, StyleRule::Display(DisplayType::Inline)),
-        _ => Item::build(),
-    };
+    let blocks = styles.get(&root).map(|e| e.as_slice()).unwrap_or(&[]);
+    let mut item = Item::build();
+    for (tag, style) in declared_styles(system, root, blocks, css) {
+        item = item.style(tag, style);
+    }
 
     let mut children = vec![];
-    let mut is_first = true;
     for component_id in components {
         let component = system.get_component(*component_id).unwrap();
-        if let Some(style_list) = styles.get(&root) {
-            for block_id in style_list {
-                let block = &css.blocks[*block_id];
-                let mut genertated = generate_from(&block.rules);
-                for style in genertated.drain(..) {
-                    item = item.style(style.0, style.1);
-                }
-            }
-        }
         match component {
-            DomComponent::Element(el) => {
+            DomComponent::Element(_) => {
                 children.push(build_recursively(system, *component_id, styles, css));
             },
             DomComponent::Text(t) => {
-                let prepend = if t.text.chars().next() == Some(' ') && !is_first { " " } else { "" };
-                let append = if t.text.chars().last() == Some(' ') { " " } else { "" };
-
-                let target = format!("{}{}{}", prepend, crate::util::remove_trailing_spaces(t.text.as_str()), append);
-                children.push(Item::build().component(Ui::Text { text: target }));
+                // Whitespace is kept as is, layout collapses it according to `white-space`
+                children.push(Item::build().component(Ui::Text { text: t.text.clone() }));
             }
+            DomComponent::Document(_) => unreachable!(),
         }
-        is_first = false;
     }
 
     item = item.with_children(children);
     item
 }
 
-pub fn parse_xml(path: &str) -> crate::ui::UiSystem {
-    let style_file = std::fs::read_to_string("./style.css").unwrap();
-    let mut stylesheet = match crate::css_parser::CssParser::new(style_file.as_str()).parse() {
-        Ok(x) => x,
-        Err(e) => return make_error(format!("{:?}", e), path, "./style.css")
-    };
+/// Pairs every component of the document with the element of the ui built from it
+fn map_recursively(system: &DomSystem, root: Id, ui: &UiSystem, ui_root: UiId, ids: &mut HashMap<Id, UiId>) {
+    ids.insert(root, ui_root);
+    let children = &ui.things.get(ui_root).unwrap().children;
+    for (component, child) in system.firstlevel_components(root).unwrap().iter().zip(children.iter()) {
+        map_recursively(system, *component, ui, *child, ids);
+    }
+}
+
+/// A document together with its stylesheet, remembers which blocks match which elements
+/// so only the elements affected by a change have to be matched again
+pub struct StyledDocument {
+    pub dom: DomSystem,
+    css: Css,
+    /// The markup the document was parsed from
+    source: String,
+    /// Indices of the matching blocks for every element, in stylesheet order
+    styles_for_elements: HashMap<Id, Vec<usize>>,
+    /// Element of the ui from `build_ui` for every component. Emptied when the tree or its text changes,
+    /// the ui can't follow that and has to be built again
+    ui_ids: HashMap<Id, UiId>,
+}
 
-    let file = match std::fs::read_to_string(path) {
-        Ok(x) => x,
-        Err(e) => return make_error(e.to_string(), path, "./style.css")
-    };
+impl StyledDocument {
+    pub fn new(dom: DomSystem, css: Css, source: String) -> Self {
+        let mut styles_for_elements: HashMap<Id, Vec<usize>> = HashMap::new();
 
-    // Create a DOM system
-    let mut system = match DomSystem::from_xml(&file) {
-        Ok(x) => x,
-        Err(e) => return make_error(e.to_string(), path, "./style.css")
-    };
-
-    let mut styles_for_elements: HashMap<Id, Vec<usize>> = HashMap::new();
-
-    for (index, block) in stylesheet.blocks.iter().enumerate() {
-        let matching = match_selector_against_dom(&block.selector, &system);
-        for i in matching {
-            if styles_for_elements.contains_key(&i) {
-                styles_for_elements.get_mut(&i).unwrap().push(index)
+        for (index, block) in css.blocks.iter().enumerate() {
+            let matching = block.selector.query(&dom);
+            for i in matching {
+                styles_for_elements.entry(i).or_default().push(index);
             }
-            else {
-                styles_for_elements.insert(i, vec![index]);
+        }
+
+        Self { dom, css, source, styles_for_elements, ui_ids: HashMap::new() }
+    }
+
+    pub fn css(&self) -> &Css {
+        &self.css
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Indices of the blocks in the stylesheet that match the element
+    pub fn matched_blocks(&self, id: Id) -> &[usize] {
+        self.styles_for_elements.get(&id).map(|e| e.as_slice()).unwrap_or(&[])
+    }
+
+    fn rematch(&mut self, id: Id) {
+        let matching: Vec<usize> = self.css.blocks.iter()
+            .enumerate()
+            .filter(|(_, block)| block.selector.matches(&self.dom, id))
+            .map(|(index, _)| index)
+            .collect();
+        if matching.is_empty() {
+            self.styles_for_elements.remove(&id);
+        }
+        else {
+            self.styles_for_elements.insert(id, matching);
+        }
+    }
+
+    /// Matches again the elements changed since the last call, returns the elements that were restyled.
+    /// Selectors only look at the element itself, so its subtree doesn't need to be matched again
+    pub fn restyle(&mut self) -> BTreeSet<Id> {
+        let mut affected = BTreeSet::new();
+        for record in self.dom.take_mutations() {
+            match record {
+                MutationRecord::Attribute { target, .. } | MutationRecord::Unknown { target } => {
+                    affected.insert(target);
+                },
+                MutationRecord::ChildList { added, removed, .. } => {
+                    self.ui_ids.clear();
+                    for id in removed {
+                        affected.remove(&id);
+                        self.styles_for_elements.remove(&id);
+                    }
+                    for id in added {
+                        affected.insert(id);
+                        affected.extend(self.dom.compose_children(id).unwrap_or_default());
+                    }
+                },
+                MutationRecord::Text { .. } => self.ui_ids.clear()
             }
         }
+        // Removed descendants are no longer in the document
+        let dom = &self.dom;
+        self.styles_for_elements.retain(|id, _| dom.get_element(*id).is_some());
+        affected.retain(|id| dom.get_element(*id).is_some());
+        for id in affected.iter() {
+            self.rematch(*id);
+        }
+        affected
     }
 
-    let items = build_recursively(&system, system.root(), &styles_for_elements, &stylesheet);
-    items.into_ui()
+    /// Replaces the stylesheet, only the elements matched by changed blocks are matched again.
+    /// Returns the elements whose styles might have changed
+    pub fn set_stylesheet(&mut self, css: Css) -> BTreeSet<Id> {
+        let changed: Vec<usize> = (0..self.css.blocks.len().max(css.blocks.len()))
+            .filter(|i| self.css.blocks.get(*i) != css.blocks.get(*i))
+            .collect();
+
+        let mut affected: BTreeSet<Id> = self.styles_for_elements.iter()
+            .filter(|(_, blocks)| blocks.iter().any(|e| changed.contains(e)))
+            .map(|(id, _)| *id)
+            .collect();
+        for index in changed.iter() {
+            if let Some(block) = css.blocks.get(*index) {
+                affected.extend(block.selector.query(&self.dom));
+            }
+        }
+
+        self.css = css;
+        for id in affected.iter() {
+            self.rematch(*id);
+        }
+        affected
+    }
+
+    pub fn build_ui(&mut self) -> UiSystem {
+        let ui = build_recursively(&self.dom, self.dom.root(), &self.styles_for_elements, &self.css).into_ui();
+        self.ui_ids.clear();
+        map_recursively(&self.dom, self.dom.root(), &ui, ui.root, &mut self.ui_ids);
+        ui
+    }
+
+    /// Declares the styles of the restyled elements again on `ui`, which was built with `build_ui`.
+    /// Returns false when the document changed in a way the ui can't follow, then it has to be built again
+    pub fn update_ui(&self, ui: &mut UiSystem, affected: &BTreeSet<Id>) -> bool {
+        if self.ui_ids.is_empty() {
+            return false
+        }
+        for id in affected {
+            // Elements the ui wasn't built with can't be updated in it
+            let ui_id = match self.ui_ids.get(id) {
+                Some(ui_id) => *ui_id,
+                None => return false
+            };
+            let styles = declared_styles(&self.dom, *id, self.matched_blocks(*id), &self.css);
+            ui.set_styles(ui_id, styles);
+        }
+        true
+    }
+}
+
+/// Returns the restyled elements when `current` was kept, `None` when the document was parsed again
+fn load_document(path: &str, pathcss: &str, current: &mut Option<StyledDocument>) -> Result<Option<BTreeSet<Id>>, String> {
+    let style_file = std::fs::read_to_string(pathcss).map_err(|e| e.to_string())?;
+    let stylesheet = crate::css_parser::CssParser::new(style_file.as_str()).parse()
+        .map_err(|e| format!("{:?}", e))?;
+
+    let file = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+    // The markup didn't change, so there's no need to match the whole document again
+    if let Some(document) = current {
+        if document.source() == file {
+            let mut affected = document.set_stylesheet(stylesheet);
+            affected.extend(document.restyle());
+            return Ok(Some(affected));
+        }
+    }
+
+    // Create a DOM system
+    let system = DomSystem::from_xml(&file).map_err(|e| e.to_string())?;
+    *current = Some(StyledDocument::new(system, stylesheet, file));
+    Ok(None)
+}
+
+/// Loads the fonts declared with `@font-face`, sources are relative to the stylesheet at `pathcss`
+fn load_fonts(css: &Css, pathcss: &str) -> Result<Fonts, String> {
+    let directory = std::path::Path::new(pathcss).parent().unwrap_or(std::path::Path::new(""));
+    let mut fonts = Fonts::new();
+    for face in generate_font_faces(css) {
+        fonts.add(&face.family, Font::load(&directory.join(&face.src).to_string_lossy())?);
+    }
+    Ok(fonts)
+}
+
+/// Reloads the document and the stylesheet into `ui`. When the markup didn't change only the restyled elements
+/// of `current` are updated in `ui`, which keeps the rest of it together with its layout.
+/// On error `current` keeps its document and `ui` becomes the error screen
+pub fn reload_xml(path: &str, pathcss: &str, current: &mut Option<StyledDocument>, ui: &mut UiSystem) {
+    let faces = current.as_ref().map(|e| generate_font_faces(e.css()));
+    let result = load_document(path, pathcss, current).and_then(|affected| {
+        let document = current.as_mut().unwrap();
+        let updated = affected.is_some_and(|affected| document.update_ui(ui, &affected));
+        if !updated {
+            *ui = document.build_ui();
+        }
+        // Setting fonts lays out everything again, so they are only loaded when needed
+        if !updated || faces != Some(generate_font_faces(document.css())) {
+            ui.set_fonts(load_fonts(document.css(), pathcss)?);
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        // The error screen isn't built from the document, the next reload has to build the ui again
+        if let Some(document) = current {
+            document.ui_ids.clear();
+        }
+        *ui = make_error(e, path, pathcss);
+    }
+}
+
+pub fn parse_xml(path: &str) -> crate::ui::UiSystem {
+    let mut ui = Item::build().into_ui();
+    reload_xml(path, "./style.css", &mut None, &mut ui);
+    ui
+}
+
+#[test]
+fn test_incremental_restyle() {
+    use crate::css_parser::CssParser;
+
+    let input = r#"<Div><Div class="a"/><Div class="b"/><Span/></Div>"#;
+    let css = CssParser::new(".a { color: red; } .b { color: blue; } Span { color: green; }").parse().unwrap();
+    let mut doc = StyledDocument::new(DomSystem::from_xml(input).unwrap(), css, input.to_string());
+    let root = doc.dom.document_element().unwrap();
+    let children = doc.dom.get_element(root).unwrap().children.clone();
+    assert_eq!(doc.matched_blocks(children[0]), &[0]);
+    assert_eq!(doc.matched_blocks(children[2]), &[2]);
+
+    // Only the changed element is matched again
+    doc.dom.add_class(children[1], "a");
+    let affected = doc.restyle();
+    assert_eq!(affected.iter().copied().collect::<Vec<_>>(), vec![children[1]]);
+    assert_eq!(doc.matched_blocks(children[1]), &[0, 1]);
+    assert!(doc.restyle().is_empty());
+
+    let added = doc.dom.parse_fragment(r#"<Div class="b"><Span/></Div>"#, root).unwrap();
+    let affected = doc.restyle();
+    assert_eq!(affected.len(), 2);
+    assert_eq!(doc.matched_blocks(added[0]), &[1]);
+
+    doc.dom.remove(added[0]);
+    doc.restyle();
+    assert_eq!(doc.matched_blocks(added[0]), &[] as &[usize]);
+
+    // Only the elements matched by the changed block are affected
+    let css = CssParser::new(".a { color: red; } .b { color: white; } Span { color: green; }").parse().unwrap();
+    let affected = doc.set_stylesheet(css);
+    assert_eq!(affected.iter().copied().collect::<Vec<_>>(), vec![children[1]]);
+
+    let css = CssParser::new(".a { color: red; } .b { color: white; } Div { color: green; }").parse().unwrap();
+    let affected = doc.set_stylesheet(css);
+    assert_eq!(affected.len(), 4);
+    assert_eq!(doc.matched_blocks(children[2]), &[] as &[usize]);
+    assert_eq!(doc.matched_blocks(children[0]), &[0, 2]);
+}
+
+#[test]
+fn test_specificity_cascade() {
+    use crate::css_parser::CssParser;
+
+    let input = r#"<Div><Div id="a" class="b"/><Div class="b c"/></Div>"#;
+    let css = CssParser::new("#a { color: red; } .b { color: blue; } .b.c { color: green; } .c.b { color: white; }").parse().unwrap();
+    let mut doc = StyledDocument::new(DomSystem::from_xml(input).unwrap(), css, input.to_string());
+    let ui = doc.build_ui();
+    let element = ui.things.get(ui.root).unwrap().children[0];
+    let children = ui.things.get(element).unwrap().children.clone();
+    // The id wins over the later class, the last of two equally specific blocks wins
+    assert_eq!(ui.things.get(children[0]).unwrap().get_fg(), (255, 0, 0, 255));
+    assert_eq!(ui.things.get(children[1]).unwrap().get_fg(), (255, 255, 255, 255));
+}
+
+#[test]
+fn test_error_caret() {
+    let ui = make_error("Unexpected token\n<Div>\n   ^".to_string(), "test.xml", "style.css");
+    let frames = ui.calculate_layout(None);
+    let lines = ui.things.get(ui.root).unwrap().children[1];
+    let runs: Vec<String> = ui.things.get(lines).unwrap().children.iter()
+        .map(|line| ui.things.get(*line).unwrap().children[0])
+        .flat_map(|text| frames.iter().find(|e| e.for_id == text).unwrap().runs.iter().map(|e| e.text.clone()))
+        .collect();
+    assert_eq!(runs, vec!["Unexpected token", "<Div>", "   ^"]);
+}
+
+#[test]
+fn test_update_ui_unknown_element() {
+    use crate::css_parser::CssParser;
+
+    let input = r#"<Div><Div class="a"/></Div>"#;
+    let css = CssParser::new(".a { color: red; }").parse().unwrap();
+    let mut doc = StyledDocument::new(DomSystem::from_xml(input).unwrap(), css, input.to_string());
+    let mut ui = doc.build_ui();
+    let root = doc.dom.document_element().unwrap();
+    doc.dom.add_class(root, "a");
+    let affected = doc.restyle();
+    assert!(doc.update_ui(&mut ui, &affected));
+
+    // An element from another document isn't in the ui, so it has to be built again
+    let other = DomSystem::from_xml(r#"<Div><Div/><Div/><Div/></Div>"#).unwrap();
+    let stranger = *other.compose_children(other.root()).unwrap().last().unwrap();
+    assert!(!doc.update_ui(&mut ui, &BTreeSet::from([stranger])));
+}
+
+#[test]
+fn test_incremental_reload() {
+    let directory = std::env::temp_dir().join(format!("xogue-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let (path, pathcss) = (directory.join("test.xml"), directory.join("style.css"));
+    let (path, pathcss) = (path.to_str().unwrap(), pathcss.to_str().unwrap());
+    std::fs::write(path, r#"<Div><Div class="a"/><Div class="b"/></Div>"#).unwrap();
+    std::fs::write(pathcss, ".a { color: red; } .b { color: blue; }").unwrap();
+
+    let mut document = None;
+    let mut ui = Item::build().into_ui();
+    reload_xml(path, pathcss, &mut document, &mut ui);
+    let element = ui.things.get(ui.root).unwrap().children[0];
+    let children = ui.things.get(element).unwrap().children.clone();
+    assert_eq!(ui.things.get(children[0]).unwrap().get_fg(), (255, 0, 0, 255));
+    // Only kept when the element isn't styled from the stylesheet again
+    ui.set_style(children[1], StyleRuleTag::BackgroundColor, Style::Value(StyleRule::Color { color: (1, 2, 3, 255) }));
+
+    // Same markup, only the element matched by the changed block is updated in the same ui
+    std::fs::write(pathcss, ".a { color: blue; } .b { color: blue; }").unwrap();
+    reload_xml(path, pathcss, &mut document, &mut ui);
+    assert_eq!(ui.things.get(children[0]).unwrap().get_fg(), (0, 0, 255, 255));
+    assert_eq!(ui.things.get(children[1]).unwrap().get_bg(), (1, 2, 3, 255));
+
+    // Changed markup is built again
+    std::fs::write(path, r#"<Div><Div class="b"/></Div>"#).unwrap();
+    reload_xml(path, pathcss, &mut document, &mut ui);
+    let element = ui.things.get(ui.root).unwrap().children[0];
+    let child = ui.things.get(element).unwrap().children[0];
+    assert_eq!(ui.things.get(child).unwrap().get_bg(), (255, 255, 255, 0));
+    std::fs::remove_dir_all(&directory).unwrap();
 }